@@ -0,0 +1,382 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generates typed bindings for contracts compiled by forge, see [`bind!`].
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::PathBuf,
+};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Ident, LitStr, Token,
+};
+
+/// The arguments of [`bind!`]: the contract name and the path of its artifact.
+struct Input {
+    name: Ident,
+    artifact: LitStr,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let artifact = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(Self { name, artifact })
+    }
+}
+
+/// Generates a typed wrapper around a `Contract` from the ABI in its forge artifact, with an
+/// async method for every function of the contract.
+///
+/// ```ignore
+/// forge_testsuite::bind!(Token, "contracts/out/Token.sol/Token.json");
+///
+/// let mut token = Token::deploy_contract(&mut runner).await;
+/// let ok: bool = token.transfer(to, amount).await?;
+/// ```
+///
+/// The artifact path is relative to the crate's manifest directory, so the project must be
+/// compiled with `forge build` before the bindings are. Methods are named after the functions
+/// in snake case, numbered from the second one of a set of overloads, and call the function
+/// by its full signature. An overload whose numbered name is taken by another function is
+/// suffixed with its parameter types instead, e.g. `foo_uint256_address`. The wrapper
+/// dereferences to the `Contract`, so its other methods remain available.
+#[proc_macro]
+pub fn bind(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as Input);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(Input { name, artifact }: Input) -> syn::Result<TokenStream2> {
+    let error = |message: String| syn::Error::new(artifact.span(), message);
+    let root = env::var("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+    let path = root.join(artifact.value());
+    let json = fs::read_to_string(&path)
+        .map_err(|err| error(format!("failed to read {}: {err}", path.display())))?;
+    let json: Value = serde_json::from_str(&json)
+        .map_err(|err| error(format!("invalid artifact {}: {err}", path.display())))?;
+    let abi = json
+        .get("abi")
+        .and_then(Value::as_array)
+        .ok_or_else(|| error(format!("{} has no abi", path.display())))?;
+
+    let functions = abi.iter().filter(|item| item["type"] == "function").collect::<Vec<_>>();
+    let names = method_names(&functions).map_err(error)?;
+    let mut methods = vec![];
+    for (function, method) in functions.into_iter().zip(names) {
+        let function_name = function["name"].as_str().unwrap_or_default();
+        let method = ident(&method);
+        let inputs = params(&function["inputs"]);
+        let args = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match param["name"].as_str().unwrap_or_default() {
+                "" => format_ident!("arg{}", i),
+                name => ident(&snake_case(name)),
+            })
+            .collect::<Vec<_>>();
+        let types = inputs.iter().map(rust_type).collect::<Result<Vec<_>, _>>().map_err(error)?;
+        let outputs = params(&function["outputs"])
+            .iter()
+            .map(rust_type)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+        let output = match outputs.as_slice() {
+            [output] => output.clone(),
+            outputs => quote!((#(#outputs),*)),
+        };
+
+        let signature = format!(
+            "{function_name}({})",
+            inputs.iter().map(canonical_type).collect::<Vec<_>>().join(",")
+        );
+        let doc = format!("Calls `{signature}` on the contract.");
+        methods.push(quote! {
+            #[doc = #doc]
+            pub async fn #method(
+                &mut self,
+                #(#args: #types),*
+            ) -> Result<#output, ::forge_testsuite::CallError> {
+                self.0.call_sig(#signature, (#(#args,)*)).await
+            }
+        });
+    }
+
+    let contract = name.to_string();
+    let doc = format!("Typed bindings for the `{contract}` contract.");
+    let deploy_doc = format!("Deploys `{contract}` with the runner, see `Runner::deploy`.");
+    let deploy = ident(DEPLOY);
+    let path = path.to_string_lossy().into_owned();
+    Ok(quote! {
+        #[doc = #doc]
+        pub struct #name(pub ::forge_testsuite::Contract);
+
+        impl #name {
+            #[doc = #deploy_doc]
+            pub async fn #deploy(runner: &mut ::forge_testsuite::Runner) -> Self {
+                Self(runner.deploy(#contract).await)
+            }
+
+            #(#methods)*
+        }
+
+        impl From<::forge_testsuite::Contract> for #name {
+            fn from(contract: ::forge_testsuite::Contract) -> Self {
+                Self(contract)
+            }
+        }
+
+        impl ::std::ops::Deref for #name {
+            type Target = ::forge_testsuite::Contract;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl ::std::ops::DerefMut for #name {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+
+        // regenerates the bindings whenever the artifact changes.
+        const _: &str = include_str!(#path);
+    })
+}
+
+/// The name of the generated method deploying the contract.
+const DEPLOY: &str = "deploy_contract";
+
+/// The method name of every function, in order. Overloads share a name, so all but the first
+/// are numbered, or suffixed with their parameter types if the numbered name is taken.
+fn method_names(functions: &[&Value]) -> Result<Vec<String>, String> {
+    // the unnumbered names are reserved first, so a numbered overload can't take them.
+    let mut names = BTreeMap::<String, &str>::new();
+    for function in functions {
+        let function_name = function["name"].as_str().unwrap_or_default();
+        let name = snake_case(function_name);
+        if name == DEPLOY {
+            return Err(format!("function {function_name} clashes with the generated {DEPLOY}"))
+        }
+        if let Some(other) = names.insert(name.clone(), function_name) {
+            if other != function_name {
+                return Err(format!("functions {other} and {function_name} both bind to {name}"))
+            }
+        }
+    }
+
+    let mut taken = names.into_keys().collect::<BTreeSet<_>>();
+    let mut overloads = BTreeMap::<&str, usize>::new();
+    let mut methods = vec![];
+    for function in functions {
+        let function_name = function["name"].as_str().unwrap_or_default();
+        let name = snake_case(function_name);
+        let overload = overloads.entry(function_name).or_default();
+        *overload += 1;
+        if *overload == 1 {
+            methods.push(name);
+            continue
+        }
+
+        let numbered = format!("{name}{}", *overload - 1);
+        let method = if taken.contains(&numbered) {
+            let types = params(&function["inputs"]).iter().map(canonical_type).collect::<Vec<_>>();
+            let suffix = types
+                .join(",")
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("_");
+            format!("{name}_{suffix}")
+        } else {
+            numbered
+        };
+        if !taken.insert(method.clone()) {
+            return Err(format!("overload of {function_name} can't be bound to {method}"))
+        }
+        methods.push(method);
+    }
+    Ok(methods)
+}
+
+/// The parameters of an ABI item, empty if it has none.
+fn params(params: &Value) -> Vec<Value> {
+    params.as_array().cloned().unwrap_or_default()
+}
+
+/// The rust type an ABI parameter is encoded from and decoded into.
+fn rust_type(param: &Value) -> Result<TokenStream2, String> {
+    let ty = param["type"].as_str().unwrap_or_default();
+    array_type(ty, &param["components"])
+}
+
+fn array_type(ty: &str, components: &Value) -> Result<TokenStream2, String> {
+    if let Some(inner) = ty.strip_suffix(']') {
+        let (inner, size) = inner.rsplit_once('[').ok_or_else(|| format!("invalid type {ty}"))?;
+        let inner = array_type(inner, components)?;
+        if size.is_empty() {
+            return Ok(quote!(::std::vec::Vec<#inner>))
+        }
+        let size = size.parse::<usize>().map_err(|_| format!("invalid type {ty}"))?;
+        return Ok(quote!([#inner; #size]))
+    }
+
+    let types = quote!(::forge_testsuite::__private);
+    Ok(match ty {
+        "tuple" => {
+            let components =
+                params(components).iter().map(rust_type).collect::<Result<Vec<_>, _>>()?;
+            quote!((#(#components,)*))
+        },
+        "address" => quote!(#types::Address),
+        "bool" => quote!(bool),
+        "string" => quote!(::std::string::String),
+        "bytes" => quote!(#types::Bytes),
+        _ if ty.starts_with("bytes") => {
+            let size =
+                ty["bytes".len()..].parse::<usize>().map_err(|_| format!("invalid type {ty}"))?;
+            quote!([u8; #size])
+        },
+        _ if ty.starts_with("uint") => quote!(#types::U256),
+        _ if ty.starts_with("int") => quote!(#types::I256),
+        _ => return Err(format!("unsupported type {ty}")),
+    })
+}
+
+/// The type of an ABI parameter as it appears in a function signature, with tuples expanded.
+fn canonical_type(param: &Value) -> String {
+    let ty = param["type"].as_str().unwrap_or_default();
+    match ty.strip_prefix("tuple") {
+        Some(suffix) => {
+            let components = params(&param["components"]);
+            let components = components.iter().map(canonical_type).collect::<Vec<_>>();
+            format!("({}){suffix}", components.join(","))
+        },
+        None => ty.to_string(),
+    }
+}
+
+/// Converts a solidity identifier to snake case, e.g. `balanceOf` to `balance_of`.
+fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lowercase = chars.get(i + 1).map_or(false, |next| next.is_lowercase());
+            if prev.is_lowercase() ||
+                prev.is_ascii_digit() ||
+                (prev.is_uppercase() && next_lowercase)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// An identifier for `name`, suffixed with an underscore if it's a rust keyword.
+fn ident(name: &str) -> Ident {
+    syn::parse_str::<Ident>(name).unwrap_or_else(|_| format_ident!("{}_", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn snake_cases_identifiers() {
+        assert_eq!(snake_case("balanceOf"), "balance_of");
+        assert_eq!(snake_case("transferFrom"), "transfer_from");
+        assert_eq!(snake_case("DOMAIN_SEPARATOR"), "domain_separator");
+        assert_eq!(snake_case("getURIPrefix"), "get_uri_prefix");
+        assert_eq!(snake_case("toUint256At"), "to_uint256_at");
+        assert_eq!(snake_case("owner"), "owner");
+    }
+
+    #[test]
+    fn maps_array_types() {
+        let types = quote!(::forge_testsuite::__private);
+        let cases = [
+            ("uint256", quote!(#types::U256)),
+            ("int8", quote!(#types::I256)),
+            ("address[]", quote!(::std::vec::Vec<#types::Address>)),
+            ("bytes32[2]", quote!([[u8; 32usize]; 2usize])),
+            ("bool[][3]", quote!([::std::vec::Vec<bool>; 3usize])),
+        ];
+        for (ty, expected) in cases {
+            assert_eq!(array_type(ty, &Value::Null).unwrap().to_string(), expected.to_string());
+        }
+
+        let components = json!([{ "type": "address" }, { "type": "string" }]);
+        assert_eq!(
+            array_type("tuple[]", &components).unwrap().to_string(),
+            quote!(::std::vec::Vec<(#types::Address, ::std::string::String,)>).to_string()
+        );
+
+        assert!(array_type("fixed128x18", &Value::Null).is_err());
+        assert!(array_type("uint256[x]", &Value::Null).is_err());
+    }
+
+    #[test]
+    fn expands_tuples_in_canonical_types() {
+        assert_eq!(canonical_type(&json!({ "type": "uint256[]" })), "uint256[]");
+
+        let param = json!({
+            "type": "tuple[2]",
+            "components": [
+                { "type": "address" },
+                { "type": "tuple", "components": [{ "type": "bytes" }, { "type": "bool" }] },
+            ],
+        });
+        assert_eq!(canonical_type(&param), "(address,(bytes,bool))[2]");
+    }
+
+    #[test]
+    fn names_overloads_around_existing_functions() {
+        let function = |name: &str, inputs: &[&str]| {
+            let inputs = inputs.iter().map(|ty| json!({ "type": ty })).collect::<Vec<_>>();
+            json!({ "type": "function", "name": name, "inputs": inputs })
+        };
+        let functions = [
+            function("mint", &["uint256"]),
+            function("mint", &["address", "uint256"]),
+            function("transfer", &["address"]),
+            function("transfer", &["address", "uint256[]"]),
+            function("transfer1", &[]),
+        ];
+        let functions = functions.iter().collect::<Vec<_>>();
+        assert_eq!(
+            method_names(&functions).unwrap(),
+            ["mint", "mint1", "transfer", "transfer_address_uint256", "transfer1"]
+        );
+
+        let clashing = [function("setOwner", &[]), function("set_owner", &[])];
+        assert!(method_names(&clashing.iter().collect::<Vec<_>>()).is_err());
+        let deploy = [function("deployContract", &[])];
+        assert!(method_names(&deploy.iter().collect::<Vec<_>>()).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builder for configuring a [`Runner`].
+
+use crate::{cache, coverage::CoverageSources, Runner, RunnerError, Verbosity};
+use ethers::types::{H256, U256};
+use ethers_solc::{remappings::Remapping, Project, ProjectPathsConfig, Solc, SolcConfig};
+use forge::{
+    executor::{
+        inspector::CheatsConfig,
+        opts::{Env, EvmOpts},
+    },
+    MultiContractRunnerBuilder,
+};
+use foundry_config::{fs_permissions::PathPermission, Config, FsPermissions, SolcReq};
+use foundry_evm::{executor::SpecId, revm::primitives::MAX_CODE_SIZE, Address};
+use semver::Version;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Configures and compiles a foundry project into a [`Runner`].
+#[derive(Debug, Clone)]
+pub struct RunnerBuilder {
+    /// The root of the foundry project
+    root: PathBuf,
+    /// The source directory, `src` if not set
+    sources: Option<PathBuf>,
+    /// The artifacts directory, `out` if not set
+    artifacts: Option<PathBuf>,
+    /// The library directories, `lib` if none are set
+    libs: Vec<PathBuf>,
+    /// The EVM spec contracts are executed with, from `foundry.toml` if not set
+    spec: Option<SpecId>,
+    /// Whether the solc optimizer is enabled, from `foundry.toml` if not set
+    optimizer: Option<bool>,
+    /// The number of optimizer runs, from `foundry.toml` or the solc default if not set
+    optimizer_runs: Option<u32>,
+    /// Whether sources are compiled through the IR pipeline
+    via_ir: bool,
+    /// The solc version to compile with, from `foundry.toml`, which may also point to a local
+    /// solc binary, or auto detected from the sources if not set
+    solc_version: Option<Version>,
+    /// The `tx.origin` of calls, the sender if not set
+    tx_origin: Option<Address>,
+    /// Remappings added on top of the ones in `foundry.toml` and `remappings.txt`
+    remappings: Vec<(String, PathBuf)>,
+    /// Whether gas usage is aggregated into a [`GasReport`](crate::GasReport)
+    gas_report: bool,
+    /// Whether line coverage is collected for every call
+    coverage: bool,
+    /// What is logged for every call
+    verbosity: Verbosity,
+    /// The seconds `block.timestamp` advances by before every call, zero to disable
+    block_time: u64,
+    /// Whether `block.number` also advances by one with `block.timestamp`
+    roll_blocks: bool,
+    /// The `block.prevrandao` of calls, zero if not set
+    prevrandao: Option<H256>,
+    /// Accounts funded with the given balance when the executor is created
+    accounts: Vec<(Address, U256)>,
+    /// Paths cheatcodes may access besides the project root, which is always read-write
+    fs_permissions: Vec<PathPermission>,
+    /// The EVM executor options
+    evm_opts: EvmOpts,
+}
+
+impl RunnerBuilder {
+    /// Creates a builder for the foundry project at `root`. The compiler settings, remappings and
+    /// EVM spec are read from the `foundry.toml` at the root if there is one, anything set on
+    /// the builder takes precedence.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let evm_opts = EvmOpts {
+            env: Env {
+                gas_limit: u64::MAX,
+                chain_id: Some(foundry_common::DEV_CHAIN_ID),
+                tx_origin: Config::DEFAULT_SENDER,
+                block_number: 1,
+                block_timestamp: 1,
+                code_size_limit: Some(MAX_CODE_SIZE),
+                ..Default::default()
+            },
+            sender: Config::DEFAULT_SENDER,
+            initial_balance: U256::MAX,
+            ffi: false,
+            memory_limit: 2u64.pow(24),
+            ..Default::default()
+        };
+
+        Self {
+            root: root.into(),
+            sources: None,
+            artifacts: None,
+            libs: vec![],
+            spec: None,
+            optimizer: None,
+            optimizer_runs: None,
+            via_ir: false,
+            solc_version: None,
+            tx_origin: None,
+            remappings: vec![],
+            gas_report: false,
+            coverage: false,
+            verbosity: Verbosity::default(),
+            block_time: 0,
+            roll_blocks: true,
+            prevrandao: None,
+            accounts: vec![],
+            fs_permissions: vec![],
+            evm_opts,
+        }
+    }
+
+    /// Sets the root of the foundry project.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Sets the directory containing the contract sources, relative to the project root.
+    /// Defaults to `src`.
+    pub fn sources(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.sources = Some(dir.into());
+        self
+    }
+
+    /// Sets the directory compiled artifacts are written to, relative to the project root.
+    /// Defaults to `out`.
+    pub fn artifacts(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.artifacts = Some(dir.into());
+        self
+    }
+
+    /// Adds a directory containing libraries, relative to the project root. Defaults to `lib`
+    /// if none are added.
+    pub fn lib(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.libs.push(dir.into());
+        self
+    }
+
+    /// Allows cheatcodes like `vm.readFile` to read from `path`, relative to the project root,
+    /// in addition to the root itself.
+    pub fn allow_read(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fs_permissions.push(PathPermission::read(path.into()));
+        self
+    }
+
+    /// Allows cheatcodes like `vm.writeFile` to write to `path`, relative to the project root,
+    /// in addition to the root itself.
+    pub fn allow_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fs_permissions.push(PathPermission::write(path.into()));
+        self
+    }
+
+    /// Sets the EVM spec contracts are executed with, defaults to the `evm_version` in
+    /// `foundry.toml` or [`SpecId::SHANGHAI`]. Fails with [`RunnerError::UnsupportedSpec`] for
+    /// Cancun and later, whose opcodes the EVM this crate runs on doesn't implement.
+    pub fn spec(mut self, spec: SpecId) -> Result<Self, RunnerError> {
+        self.spec = Some(supported_spec(spec)?);
+        Ok(self)
+    }
+
+    /// Enables or disables the solc optimizer, enabled by default.
+    pub fn optimizer(mut self, enabled: bool) -> Self {
+        self.optimizer = Some(enabled);
+        self
+    }
+
+    /// Sets the number of optimizer runs, ignored if the optimizer is disabled.
+    pub fn optimizer_runs(mut self, runs: u32) -> Self {
+        self.optimizer_runs = Some(runs);
+        self
+    }
+
+    /// Compiles through the IR pipeline, which avoids most stack too deep errors. Disabled by
+    /// default.
+    pub fn via_ir(mut self, enabled: bool) -> Self {
+        self.via_ir = enabled;
+        self
+    }
+
+    /// Compiles with the given solc version instead of detecting it from the pragmas, installing
+    /// it if it's not available yet.
+    pub fn solc_version(mut self, version: Version) -> Self {
+        self.solc_version = Some(version);
+        self
+    }
+
+    /// Adds a remapping from `name` to `path`, relative to the project root. Takes precedence
+    /// over a remapping with the same name in `remappings.txt`.
+    pub fn remapping(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.remappings.push((name.into(), path.into()));
+        self
+    }
+
+    /// Enables or disables the `ffi` cheatcode, disabled by default since it allows contracts to
+    /// execute arbitrary commands.
+    pub fn ffi(mut self, enabled: bool) -> Self {
+        self.evm_opts.ffi = enabled;
+        self
+    }
+
+    /// Sets the maximum size in bytes of deployed contract code, defaults to the EIP-170 limit of
+    /// 24576 bytes. Deploying a larger contract fails, pass `usize::MAX` to lift the limit.
+    pub fn code_size_limit(mut self, limit: usize) -> Self {
+        self.evm_opts.env.code_size_limit = Some(limit);
+        self
+    }
+
+    /// Sets the maximum memory in bytes a call can allocate, defaults to 16 MiB. Raising it
+    /// allows memory heavy computations like large pairing checks, at the cost of no longer
+    /// catching accidentally unbounded memory use.
+    pub fn memory_limit(mut self, limit: u64) -> Self {
+        self.evm_opts.memory_limit = limit;
+        self
+    }
+
+    /// Sets the gas limit for deployments and calls.
+    pub fn gas_limit(mut self, gas_limit: u64) -> Self {
+        self.evm_opts.env.gas_limit = gas_limit;
+        self
+    }
+
+    /// Sets the gas price of deployments and calls, raised to the base fee if it's lower.
+    pub fn gas_price(mut self, gas_price: U256) -> Self {
+        self.evm_opts.env.gas_price = Some(gas_price.min(U256::from(u64::MAX)).as_u64());
+        self
+    }
+
+    /// Sets `block.basefee`, defaults to zero.
+    pub fn base_fee(mut self, base_fee: U256) -> Self {
+        self.evm_opts.env.block_base_fee_per_gas = base_fee.min(U256::from(u64::MAX)).as_u64();
+        self
+    }
+
+    /// Sets the chain id, defaults to [`DEV_CHAIN_ID`](foundry_common::DEV_CHAIN_ID).
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.evm_opts.env.chain_id = Some(chain_id);
+        self
+    }
+
+    /// Sets `block.coinbase`, defaults to the zero address.
+    pub fn coinbase(mut self, coinbase: Address) -> Self {
+        self.evm_opts.env.block_coinbase = coinbase;
+        self
+    }
+
+    /// Sets `block.prevrandao`, or `block.difficulty` under specs before the merge. Defaults to
+    /// zero.
+    pub fn prevrandao(mut self, prevrandao: H256) -> Self {
+        self.prevrandao = Some(prevrandao);
+        self
+    }
+
+    /// Sets the default sender used to deploy and call contracts.
+    pub fn sender(mut self, sender: Address) -> Self {
+        self.evm_opts.sender = sender;
+        self
+    }
+
+    /// Registers an account funded with `balance` wei, e.g. an actor of a multi-party scenario.
+    /// Can be called repeatedly, registering an account again replaces its balance. See
+    /// [`Contract::accounts`](crate::Contract::accounts).
+    pub fn account(mut self, address: Address, balance: U256) -> Self {
+        self.accounts.retain(|(account, _)| *account != address);
+        self.accounts.push((address, balance));
+        self
+    }
+
+    /// Sets the `tx.origin` of calls, defaults to the sender. Calls with an origin other than
+    /// their sender are relayed through a forwarder contract, whose overhead is included in the
+    /// gas used.
+    pub fn tx_origin(mut self, origin: Address) -> Self {
+        self.tx_origin = Some(origin);
+        self
+    }
+
+    /// Sets the ether balance the sender and deployed contracts start with, defaults to
+    /// `U256::MAX`.
+    pub fn initial_balance(mut self, balance: U256) -> Self {
+        self.evm_opts.initial_balance = balance;
+        self
+    }
+
+    /// Enables aggregating the gas used by every call into a report, see
+    /// [`Runner::gas_report`].
+    pub fn gas_report(mut self, enabled: bool) -> Self {
+        self.gas_report = enabled;
+        self
+    }
+
+    /// Enables collecting line coverage for every call, see [`Contract::coverage`]. Sources
+    /// are always recompiled when enabled, disabling the optimizer gives more accurate results.
+    ///
+    /// [`Contract::coverage`]: crate::Contract::coverage
+    pub fn coverage(mut self, enabled: bool) -> Self {
+        self.coverage = enabled;
+        self
+    }
+
+    /// Sets what is logged for every call, like the `-v` flags of `forge test`. Defaults to
+    /// [`Verbosity::Logs`].
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Mines every call in a block of its own, advancing `block.number` by one and
+    /// `block.timestamp` by `seconds` before each call that commits its changes. Disabled when
+    /// zero, the default. See [`RunnerBuilder::roll_blocks`] to only advance the timestamp.
+    pub fn block_time(mut self, seconds: u64) -> Self {
+        self.block_time = seconds;
+        self
+    }
+
+    /// Whether calls advancing the chain by the [`RunnerBuilder::block_time`] also advance
+    /// `block.number`, enabled by default. When disabled only `block.timestamp` moves, for
+    /// contracts that measure time in seconds across calls within the same block.
+    pub fn roll_blocks(mut self, enabled: bool) -> Self {
+        self.roll_blocks = enabled;
+        self
+    }
+
+    /// Forks the state of the chain at `rpc_url` at the given block, or the latest block if
+    /// none is provided.
+    pub fn fork(mut self, rpc_url: impl Into<String>, block: Option<u64>) -> Self {
+        self.evm_opts.fork_url = Some(rpc_url.into());
+        self.evm_opts.fork_block_number = block;
+        self
+    }
+
+    /// Compiles the project and builds a non-tracing runner.
+    pub fn build(self) -> Result<Runner, RunnerError> {
+        let Self {
+            root,
+            sources,
+            artifacts,
+            libs,
+            spec,
+            optimizer,
+            optimizer_runs,
+            via_ir,
+            solc_version,
+            tx_origin,
+            remappings,
+            gas_report,
+            coverage,
+            verbosity,
+            block_time,
+            roll_blocks,
+            prevrandao,
+            accounts,
+            fs_permissions,
+            mut evm_opts,
+        } = self;
+        if !root.exists() {
+            return Err(RunnerError::MissingRoot(root))
+        }
+
+        // settings not set on the builder fall back to foundry.toml, if there is one.
+        let file = root
+            .join(Config::FILE_NAME)
+            .exists()
+            .then(|| Config::try_from(Config::figment_with_root(&root)))
+            .transpose()
+            .map_err(|err| RunnerError::Config(err.into()))?;
+        let spec = spec.or(file.as_ref().map(Config::evm_spec_id)).unwrap_or(SpecId::SHANGHAI);
+        let spec = supported_spec(spec)?;
+        let optimizer = optimizer.or(file.as_ref().map(|file| file.optimizer)).unwrap_or(true);
+        let optimizer_runs = optimizer_runs
+            .or(file.as_ref().map(|file| file.optimizer_runs.try_into().unwrap_or(u32::MAX)));
+        let solc = match (solc_version, file.as_ref().and_then(|file| file.solc.clone())) {
+            (Some(version), _) | (None, Some(SolcReq::Version(version))) => Some(
+                Solc::find_or_install_svm_version(version.to_string())
+                    .map_err(|err| RunnerError::SolcVersion(version, err))?,
+            ),
+            (None, Some(SolcReq::Local(path))) => Some(Solc::new(root.join(path))),
+            (None, None) => None,
+        };
+
+        let mut paths = ProjectPathsConfig::builder().root(root.clone());
+        if let Some(sources) = sources {
+            paths = paths.sources(root.join(sources));
+        }
+        if let Some(artifacts) = artifacts {
+            paths = paths.artifacts(root.join(artifacts));
+        }
+        if !libs.is_empty() {
+            paths = paths.libs(libs.iter().map(|lib| root.join(lib)));
+        }
+        let mut paths = paths.build()?;
+
+        // the remappings from foundry.toml or remappings.txt, followed by the ones set on the
+        // builder.
+        let base = match &file {
+            Some(file) => file.get_all_remappings(),
+            None => parse_remappings(&root)?,
+        };
+        let remappings = remappings
+            .into_iter()
+            .map(|(name, path)| remapping(&root, &name, &path))
+            .collect::<Result<Vec<_>, _>>()?;
+        for mapping in base.into_iter().chain(remappings) {
+            paths.remappings.retain(|m| m.name != mapping.name);
+            paths.remappings.push(mapping)
+        }
+
+        let mut config = SolcConfig::builder().build();
+        config.settings.optimizer.enabled = Some(optimizer);
+        if optimizer {
+            config.settings.optimizer.runs = optimizer_runs.map(|runs| runs as usize);
+        }
+        config.settings.via_ir = via_ir.then_some(true);
+        let mut project = Project::builder()
+            .paths(paths)
+            .solc_config(config)
+            .set_auto_detect(solc.is_none())
+            // source ids are only available for freshly compiled sources.
+            .set_cached(!coverage);
+        if let Some(solc) = solc {
+            project = project.solc(solc);
+        }
+        let project = project.build()?;
+
+        let compiled = cache::compile(&project)?;
+
+        evm_opts.env.tx_origin = tx_origin.unwrap_or(evm_opts.sender);
+
+        // transactions priced below the base fee are rejected.
+        let base_fee = evm_opts.env.block_base_fee_per_gas;
+        if evm_opts.env.gas_price.unwrap_or_default() < base_fee {
+            evm_opts.env.gas_price = Some(base_fee);
+        }
+
+        let mut config = Config::with_root(root.clone());
+        config.sender = evm_opts.sender;
+        config.fs_permissions = FsPermissions::new(vec![PathPermission::read_write(root.clone())]);
+        config.allow_paths.push(root.clone());
+        for mut permission in fs_permissions {
+            permission.path = root.join(&permission.path);
+            config.allow_paths.push(permission.path.clone());
+            config.fs_permissions.permissions.push(permission);
+        }
+
+        let runner = MultiContractRunnerBuilder::default()
+            .sender(evm_opts.sender)
+            .initial_balance(evm_opts.initial_balance)
+            .with_cheats_config(CheatsConfig::new(&config, &evm_opts))
+            .evm_spec(spec)
+            .set_coverage(coverage)
+            .build(&project.paths.root, compiled.clone(), evm_opts.local_evm_env(), evm_opts)
+            .map_err(RunnerError::Build)?;
+
+        // every function in the project by selector, for rendering call traces.
+        let functions = runner
+            .contracts
+            .values()
+            .flat_map(|(abi, _, _)| abi.functions())
+            .map(|function| (function.short_signature(), function.clone()))
+            .collect();
+
+        let gas_report = gas_report.then(Default::default);
+        let coverage = coverage.then(|| Arc::new(CoverageSources::new(&compiled)));
+        Ok(Runner {
+            runner,
+            config,
+            output: compiled,
+            gas_report,
+            verbosity,
+            block_time,
+            roll_blocks,
+            prevrandao,
+            accounts,
+            coverage,
+            functions: Arc::new(functions),
+            executor: None,
+        })
+    }
+}
+
+/// Returns the spec unless it's Cancun or later. The pinned revm defines those, but implements
+/// none of the Cancun opcodes, so transient storage and the blob opcodes would halt every call.
+pub(crate) fn supported_spec(spec: SpecId) -> Result<SpecId, RunnerError> {
+    if spec >= SpecId::CANCUN {
+        return Err(RunnerError::UnsupportedSpec(spec))
+    }
+    Ok(spec)
+}
+
+/// Parses the remappings in `remappings.txt` at the project root, if it exists.
+fn parse_remappings(root: &Path) -> Result<Vec<Remapping>, RunnerError> {
+    let path = root.join("remappings.txt");
+    if !path.exists() {
+        return Ok(vec![])
+    }
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| RunnerError::Remappings(format!("{}: {err}", path.display())))?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, path) = line
+                .split_once('=')
+                .ok_or_else(|| RunnerError::Remappings(format!("malformed remapping: {line}")))?;
+            remapping(root, name, Path::new(path))
+        })
+        .collect()
+}
+
+/// Creates a remapping from `name` to `path`, relative to the project root.
+fn remapping(root: &Path, name: &str, path: &Path) -> Result<Remapping, RunnerError> {
+    let path = root
+        .join(path)
+        .into_os_string()
+        .into_string()
+        .map_err(|path| RunnerError::Remappings(format!("invalid remapping path: {path:?}")))?;
+
+    Ok(Remapping { context: None, name: name.to_string(), path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn project_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn parses_remappings_relative_to_root() {
+        let root = project_root("parses-remappings");
+        fs::write(
+            root.join("remappings.txt"),
+            "forge-std/=lib/forge-std/src/\n\n@openzeppelin/=lib/openzeppelin-contracts/\n",
+        )
+        .unwrap();
+
+        let remappings = parse_remappings(&root).unwrap();
+        let remappings = remappings
+            .iter()
+            .map(|remapping| (remapping.name.as_str(), PathBuf::from(&remapping.path)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            remappings,
+            vec![
+                ("forge-std/", root.join("lib/forge-std/src/")),
+                ("@openzeppelin/", root.join("lib/openzeppelin-contracts/")),
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn remappings_are_optional() {
+        let root = project_root("remappings-are-optional");
+        assert!(parse_remappings(&root).unwrap().is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_remappings() {
+        let root = project_root("rejects-malformed-remappings");
+        fs::write(root.join("remappings.txt"), "forge-std/\n").unwrap();
+
+        let err = parse_remappings(&root).unwrap_err();
+        assert!(
+            matches!(&err, RunnerError::Remappings(msg) if msg.contains("forge-std/")),
+            "{err}"
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_cancun_specs() {
+        let builder = RunnerBuilder::new(project_root("rejects-cancun"));
+        assert!(builder.clone().spec(SpecId::SHANGHAI).is_ok());
+        for spec in [SpecId::CANCUN, SpecId::LATEST] {
+            assert!(matches!(
+                builder.clone().spec(spec),
+                Err(RunnerError::UnsupportedSpec(unsupported)) if unsupported == spec
+            ));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory cache of compiled projects, shared by all runners in the process.
+
+use crate::RunnerError;
+use ethers_solc::{utils::source_files, Project, ProjectCompileOutput};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+/// Compiled outputs keyed by project root, along with the fingerprint they were compiled from.
+static ARTIFACTS: OnceLock<Mutex<HashMap<PathBuf, (u64, ProjectCompileOutput)>>> = OnceLock::new();
+
+/// Compiles the project, reusing the output of a previous compilation of the same root if
+/// neither its sources nor the compiler, its settings or the project layout have changed since.
+pub(crate) fn compile(project: &Project) -> Result<ProjectCompileOutput, RunnerError> {
+    let root = project.paths.root.clone();
+    let fingerprint = fingerprint(project);
+    let cache = ARTIFACTS.get_or_init(Default::default);
+
+    if let Some((hash, output)) = cache.lock().unwrap().get(&root) {
+        if *hash == fingerprint {
+            return Ok(output.clone())
+        }
+    }
+
+    let output = project.compile()?;
+    if output.has_compiler_errors() {
+        return Err(RunnerError::Compilation(Box::new(output)))
+    }
+
+    cache.lock().unwrap().insert(root, (fingerprint, output.clone()));
+    Ok(output)
+}
+
+/// Hashes the compiler and its settings, the remappings, the source and library directories and
+/// the contents of every source file in the project.
+fn fingerprint(project: &Project) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", project.solc_config.settings).hash(&mut hasher);
+    format!("{:?}", project.paths.remappings).hash(&mut hasher);
+    project.paths.sources.hash(&mut hasher);
+    project.paths.libraries.hash(&mut hasher);
+    project.cached.hash(&mut hasher);
+    project.auto_detect.hash(&mut hasher);
+    project.solc.solc.hash(&mut hasher);
+
+    let mut files = project.paths.input_files();
+    for lib in &project.paths.libraries {
+        files.extend(source_files(lib));
+    }
+    files.sort();
+    files.dedup();
+
+    for file in files {
+        file.hash(&mut hasher);
+        fs::read(&file).unwrap_or_default().hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
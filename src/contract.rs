@@ -0,0 +1,1762 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handle for executing calls against a deployed contract.
+
+use crate::{
+    runner::set_prevrandao, utils::word, AccountAccesses, CallError, CoverageReport, GasReport,
+    RevertReason, Runner, StateDiff, StorageAccesses,
+};
+use ethers::{
+    abi::{
+        self, Abi, AbiDecode, Detokenize, Function, LogParam, ParamType, RawLog, StateMutability,
+        Token, Tokenizable, Tokenize,
+    },
+    contract::EthEvent,
+    types::{transaction::eip2930::AccessList, Bytes, Log, H160, H256, U256},
+    utils::{hex, id},
+};
+use ethers_solc::{
+    artifacts::ast::{Node, NodeType},
+    ProjectCompileOutput,
+};
+use forge::{executor::inspector::CHEATCODE_ADDRESS, result::TestSetup};
+use foundry_common::abi::format_token;
+use foundry_evm::{
+    decode::{decode_console_logs, decode_revert},
+    executor::{EvmError, Executor, RawCallResult, SpecId},
+    revm::{
+        db::DatabaseRef,
+        interpreter::InstructionResult,
+        primitives::{AccountInfo, BlockEnv, KECCAK_EMPTY},
+    },
+    trace::{CallTrace, CallTraceArena, RawOrDecodedCall},
+    utils::{b160_to_h160, h160_to_b160, ru256_to_u256, u256_to_ru256},
+    Address, CallKind,
+};
+use proptest::{
+    strategy::Strategy,
+    test_runner::{Config as ProptestConfig, TestCaseError, TestError, TestRunner},
+};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Handle for executing a single Contract.
+///
+/// Handles are owned and share the executor of the [`Runner`](crate::Runner) that deployed them,
+/// so any number of contracts can be held at once. State changes made through one handle are
+/// visible to all the others, including snapshots and cheatcodes like [`Contract::warp`].
+pub struct Contract {
+    /// The executor shared by all contracts deployed by the same runner
+    executor: Arc<Mutex<Executor>>,
+    /// The contract name
+    pub name: String,
+    /// The contract ABI
+    abi: Abi,
+    /// The contract address
+    pub address: Address,
+    /// The default sender of calls
+    pub sender: Address,
+    /// The funded accounts registered with the runner
+    accounts: Vec<Address>,
+    /// The custom errors of all project contracts, used for decoding reverts
+    errors: Option<Abi>,
+    /// The balance callers are funded with if they can't pay for a call
+    initial_balance: U256,
+    /// The default gas limit for calls
+    gas_limit: U256,
+    /// Collects the gas used by every call, if enabled
+    gas_report: Option<Arc<Mutex<GasReport>>>,
+    /// What is logged for every call
+    verbosity: Verbosity,
+    /// The seconds the chain advances by before every committed call, zero if disabled
+    block_time: u64,
+    /// Whether advancing the chain before a call also advances `block.number`
+    roll_blocks: bool,
+    /// The line coverage accumulated across calls, if enabled
+    coverage: Option<CoverageReport>,
+    /// The call trace of the most recent call
+    last_trace: Option<CallTraceArena>,
+    /// The functions of all project contracts by selector, used for decoding call traces
+    functions: Arc<BTreeMap<[u8; 4], Function>>,
+    /// Names of the addresses labelled with [`Contract::label`]
+    labels: BTreeMap<Address, String>,
+    /// The sender of the next call only, set by [`Contract::prank`]
+    prank: Option<Address>,
+    /// The sender of all calls until [`Contract::stop_prank`], set by [`Contract::start_prank`]
+    persistent_prank: Option<Address>,
+    /// Whether calls diff the state they change, see [`Contract::record_state_diffs`]
+    recording_state_diffs: bool,
+    /// The `tx.origin` of calls, if it differs from their sender
+    origin: Option<Address>,
+    /// Whether the storage accesses of calls are recorded, see [`Contract::record_accesses`]
+    recording_accesses: bool,
+    /// The type and runtime code offset of every immutable, by name
+    immutables: BTreeMap<String, (ParamType, usize)>,
+    /// The snapshot of the state right after deployment, restored by [`Contract::reset`]
+    setup_snapshot: U256,
+    /// The number of events in `event_logs` and the block environment when each snapshot taken
+    /// through this handle was taken, by snapshot id. Neither is part of the backend snapshot.
+    snapshots: BTreeMap<U256, (usize, BlockEnv)>,
+    /// The gas used by the creation transaction
+    deploy_gas: u64,
+    /// The logs, traces, labels and coverage of the deployment and `setUp`
+    setup: TestSetup,
+    /// The events the next call must emit, in order, see [`Contract::expect_emit`]
+    expected_emits: Vec<ExpectedEmit>,
+    /// The logs emitted by deployment and every committed call since, see
+    /// [`Contract::all_events`]
+    event_logs: Vec<Log>,
+}
+
+impl Contract {
+    /// Creates a handle for the contract deployed at `address` on the runner's executor,
+    /// snapshotting the current state for [`Contract::reset`].
+    pub(crate) fn new(
+        runner: &Runner,
+        executor: Arc<Mutex<Executor>>,
+        name: &str,
+        abi: Abi,
+        address: Address,
+    ) -> Result<Self, EvmError> {
+        let multi = &runner.runner;
+        let sender = multi.sender.unwrap_or_default();
+        let origin = multi.evm_opts.env.tx_origin;
+        let mut contract = Self {
+            executor,
+            name: name.to_string(),
+            abi,
+            address,
+            sender,
+            accounts: runner.accounts.iter().map(|(account, _)| *account).collect(),
+            errors: multi.errors.clone(),
+            initial_balance: multi.evm_opts.initial_balance,
+            gas_limit: multi.evm_opts.gas_limit(),
+            gas_report: runner.gas_report.clone(),
+            verbosity: runner.verbosity,
+            block_time: runner.block_time,
+            roll_blocks: runner.roll_blocks,
+            coverage: runner.coverage.clone().map(CoverageReport::new),
+            last_trace: None,
+            functions: runner.functions.clone(),
+            labels: Default::default(),
+            prank: None,
+            persistent_prank: None,
+            recording_state_diffs: false,
+            origin: (origin != sender).then_some(origin),
+            recording_accesses: false,
+            immutables: immutables(&runner.output, name),
+            setup_snapshot: U256::zero(),
+            snapshots: Default::default(),
+            deploy_gas: 0,
+            setup: TestSetup { address, ..Default::default() },
+            expected_emits: vec![],
+            event_logs: vec![],
+        };
+        contract.setup_snapshot = contract.snapshot()?;
+        Ok(contract)
+    }
+
+    /// The accounts registered with [`RunnerBuilder::account`](crate::RunnerBuilder::account),
+    /// in registration order. Pass them to [`Contract::call_from`] to act as each of them.
+    pub fn accounts(&self) -> &[Address] {
+        &self.accounts
+    }
+
+    /// The ABI of the contract, with its functions, events and errors.
+    pub fn abi(&self) -> &Abi {
+        &self.abi
+    }
+
+    /// Locks the executor shared by all contracts deployed by the same runner, for anything not
+    /// covered by the contract handle. Calls made through any handle block until it's released.
+    pub fn executor(&self) -> MutexGuard<'_, Executor> {
+        self.executor.lock().unwrap()
+    }
+
+    /// The gas used by the creation transaction, excluding `setUp`. Zero for contracts that were
+    /// attached rather than deployed.
+    pub fn deploy_gas(&self) -> u64 {
+        self.deploy_gas
+    }
+
+    /// Everything recorded while deploying the contract and running its `setUp`: the logs, the
+    /// deployment and setup traces, the labelled addresses and the coverage of the deployment.
+    /// Only the address is set for contracts that were attached rather than deployed.
+    pub fn setup(&self) -> &TestSetup {
+        &self.setup
+    }
+
+    /// The logs emitted by the constructor and `setUp`, along with the deployment gas. Empty for
+    /// contracts that were attached rather than deployed.
+    pub fn setup_logs(&self) -> CallResult<()> {
+        CallResult {
+            result: (),
+            raw_output: Bytes::new(),
+            address: self.address,
+            logs: self.setup.logs.clone(),
+            gas_used: self.deploy_gas,
+            state_diff: Default::default(),
+            accesses: Default::default(),
+            trace: None,
+        }
+    }
+
+    /// Records the gas used by the deployment along with its setup, adding the hits it recorded
+    /// to the coverage report.
+    pub(crate) fn record_deployment(&mut self, gas_used: u64, setup: TestSetup) {
+        self.deploy_gas = gas_used;
+        self.event_logs = setup.logs.clone();
+        self.labels.extend(setup.labeled_addresses.clone());
+        if let (Some(report), Some(hits)) = (&mut self.coverage, &setup.coverage) {
+            report.merge(hits);
+        }
+        self.setup = setup;
+    }
+
+    /// Call a function on the contract with the provided arguments.
+    pub async fn call<T, R>(&mut self, func: &'static str, args: T) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        Ok(self.execute(caller, func, args, U256::zero()).await?.result)
+    }
+
+    /// Call a function on the contract with the provided arguments without requiring an async
+    /// runtime.
+    pub fn call_blocking<T, R>(&mut self, func: &'static str, args: T) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        Ok(self.dispatch(func, args, CallOpts::new(caller))?.result)
+    }
+
+    /// Call the function matching the full canonical `signature`, e.g.
+    /// `"transfer(address,uint256)"`, for selecting between overloaded functions.
+    pub async fn call_sig<T, R>(&mut self, signature: &str, args: T) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        Ok(self.dispatch(signature, args, CallOpts::new(caller))?.result)
+    }
+
+    /// Call a payable function on the contract, sending `value` wei along with the call.
+    pub async fn call_with_value<T, R>(
+        &mut self,
+        func: &str,
+        args: T,
+        value: U256,
+    ) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        Ok(self.execute(caller, func, args, value).await?.result)
+    }
+
+    /// Call a function on the contract using `caller` as the transaction sender, leaving the
+    /// default sender untouched.
+    pub async fn call_from<T, R>(
+        &mut self,
+        caller: Address,
+        func: &str,
+        args: T,
+    ) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        Ok(self.execute(caller, func, args, U256::zero()).await?.result)
+    }
+
+    /// Call a function on the contract from `caller` with the given `value`, returning the decoded
+    /// output together with the emitted logs and gas used.
+    pub async fn execute<T, R>(
+        &mut self,
+        caller: Address,
+        func: &str,
+        args: T,
+        value: U256,
+    ) -> Result<CallResult<R>, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        self.dispatch(func, args, CallOpts { value, ..CallOpts::new(caller) })
+    }
+
+    /// Call a function on the contract with `origin` as `tx.origin`, while `msg.sender` stays the
+    /// current sender. The call is relayed through a forwarder contract, whose overhead is
+    /// included in the gas used. The relayed call gets 63/64 of the gas left in the forwarder,
+    /// running out of it or halting is still reported as such rather than as a revert.
+    pub async fn call_with_origin<T, R>(
+        &mut self,
+        origin: Address,
+        func: &str,
+        args: T,
+    ) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        let opts = CallOpts { origin: Some(origin), ..CallOpts::new(caller) };
+        Ok(self.dispatch(func, args, opts)?.result)
+    }
+
+    /// Call a function on the contract, returning the decoded [`RevertReason`] as the inner
+    /// error if the call reverts. Any other failure, e.g. running out of gas, is returned as the
+    /// outer error.
+    pub async fn try_call<T, R>(
+        &mut self,
+        func: &str,
+        args: T,
+    ) -> Result<Result<R, RevertReason>, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        match self.dispatch(func, args, CallOpts::new(caller)) {
+            Ok(call) => Ok(Ok(call.result)),
+            Err(CallError::Revert(reason, _)) => Ok(Err(reason)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Executes the function of the implementation at `impl_addr` in the context of this
+    /// contract, i.e. against its storage, address and balance, like a `delegatecall` from a
+    /// proxy. The function is looked up in this contract's ABI, so attach the implementation
+    /// ABI to the proxy address with [`Runner::attach`](crate::Runner::attach).
+    pub async fn delegate_call<T, R>(
+        &mut self,
+        impl_addr: Address,
+        func: &str,
+        args: T,
+    ) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        // transactions can't delegatecall, so run the implementation code at this address.
+        let original = self.account_info(self.address)?;
+        let implementation = self.account_info(impl_addr)?;
+        self.executor().backend.insert_account_info(
+            self.address,
+            AccountInfo {
+                code: implementation.code,
+                code_hash: implementation.code_hash,
+                ..original.clone()
+            },
+        );
+
+        let caller = self.sender();
+        let result = self.dispatch(func, args, CallOpts::new(caller));
+
+        let current = self.account_info(self.address)?;
+        self.executor().backend.insert_account_info(
+            self.address,
+            AccountInfo { code: original.code, code_hash: original.code_hash, ..current },
+        );
+
+        Ok(result?.result)
+    }
+
+    /// Call a function on the contract without committing any of its state changes, useful for
+    /// reading state between mutating calls.
+    pub async fn call_static<T, R>(&mut self, func: &str, args: T) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        let opts = CallOpts { commit: false, ..CallOpts::new(caller) };
+        Ok(self.dispatch(func, args, opts)?.result)
+    }
+
+    /// Calls the function with `cases` inputs generated by the proptest `strategy`, running
+    /// `check` against every output. Failing inputs are shrunk to a minimal case, which is
+    /// returned as the error. Reverting inputs are discarded when `discard_reverts` is set and
+    /// fail otherwise. State changes made by the calls are discarded, so every case runs
+    /// against the same state.
+    pub async fn fuzz<S, R>(
+        &mut self,
+        func: &str,
+        strategy: S,
+        cases: u32,
+        discard_reverts: bool,
+        check: impl Fn(&R),
+    ) -> Result<(), TestError<S::Value>>
+    where
+        S: Strategy,
+        S::Value: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        let this = RefCell::new(self);
+        let mut runner = TestRunner::new(ProptestConfig { cases, ..Default::default() });
+
+        runner.run(&strategy, |args| {
+            let opts = CallOpts { commit: false, ..CallOpts::new(caller) };
+            match this.borrow_mut().dispatch::<_, R>(func, args, opts) {
+                Ok(call) => check(&call.result),
+                Err(CallError::Revert(reason, _)) if discard_reverts =>
+                    return Err(TestCaseError::reject(format!("reverted: {reason}"))),
+                Err(err) => return Err(TestCaseError::fail(err.to_string())),
+            }
+            Ok(())
+        })
+    }
+
+    /// Call a function on the contract with a gas limit for just this call, returning
+    /// [`CallError::OutOfGas`] if the call exceeds it, including when the limit doesn't cover
+    /// the intrinsic gas of the transaction.
+    pub async fn call_with_gas<T, R>(
+        &mut self,
+        func: &str,
+        args: T,
+        gas_limit: u64,
+    ) -> Result<R, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        let opts = CallOpts { gas_limit: Some(gas_limit), ..CallOpts::new(caller) };
+        Ok(self.dispatch(func, args, opts)?.result)
+    }
+
+    /// Estimates the gas used by calling the function with `value` wei attached, without
+    /// applying any of its effects. The call runs as the next call would, including a pending
+    /// [`Contract::prank`], which is left in place. Calls that revert or run out of gas return
+    /// the error instead of an estimate. The call isn't logged, nor recorded in the gas report,
+    /// the coverage or [`Contract::last_trace`].
+    pub async fn estimate_gas<T: Tokenize>(
+        &mut self,
+        func: &str,
+        args: T,
+        value: U256,
+    ) -> Result<u64, CallError> {
+        let caller = self.prank.or(self.persistent_prank).unwrap_or(self.sender);
+        let opts = CallOpts { value, record: false, ..CallOpts::new(caller) };
+        // commit the call so the caller is funded like a real one would be, then roll it back.
+        let snapshot = self.snapshot()?;
+        let expected_emits = std::mem::take(&mut self.expected_emits);
+        let result = self.dispatch::<T, Token>(func, args, opts);
+        self.expected_emits = expected_emits;
+        // reverting also undoes the block time advance.
+        self.revert_to(snapshot)?;
+
+        Ok(result?.gas_used)
+    }
+
+    /// Call a function with the addresses and storage slots in `access_list` warm from the start,
+    /// like an EIP-2930 transaction. The intrinsic cost of the access list is included in the gas
+    /// used, so warm and cold accesses can be compared.
+    pub async fn call_with_access_list<T, R>(
+        &mut self,
+        func: &str,
+        args: T,
+        access_list: AccessList,
+    ) -> Result<CallResult<R>, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let caller = self.sender();
+        let opts = CallOpts { access_list, ..CallOpts::new(caller) };
+        self.dispatch(func, args, opts)
+    }
+
+    /// Call a function that is expected to revert, returning the decoded revert reason. Fails if
+    /// the call succeeds or doesn't revert, e.g. because it ran out of gas.
+    pub async fn expect_revert<T>(&mut self, func: &str, args: T) -> Result<RevertReason, String>
+    where
+        T: Tokenize,
+    {
+        let caller = self.sender();
+        match self.dispatch::<T, Token>(func, args, CallOpts::new(caller)) {
+            Ok(_) => Err(format!("expected {func} to revert, but it succeeded")),
+            Err(CallError::Revert(reason, _)) => Ok(reason),
+            Err(err) => Err(format!("expected {func} to revert, but it failed: {err}")),
+        }
+    }
+
+    /// Call a function that is expected to revert with exactly the `expected` reason.
+    pub async fn expect_revert_with<T>(
+        &mut self,
+        func: &str,
+        args: T,
+        expected: RevertReason,
+    ) -> Result<(), String>
+    where
+        T: Tokenize,
+    {
+        let reason = self.expect_revert(func, args).await?;
+        if reason != expected {
+            return Err(format!("expected {func} to revert with {expected}, but got {reason}"))
+        }
+        Ok(())
+    }
+
+    /// Call a function that is expected to revert with the custom error whose selector is
+    /// `selector`, without needing its definition. Returns the error's arguments if it's defined
+    /// in the contract ABI or the project's errors, otherwise an empty list.
+    pub async fn expect_revert_selector<T>(
+        &mut self,
+        func: &str,
+        args: T,
+        selector: [u8; 4],
+    ) -> Result<Vec<Token>, String>
+    where
+        T: Tokenize,
+    {
+        let caller = self.sender();
+        let data = match self.dispatch::<T, Token>(func, args, CallOpts::new(caller)) {
+            Ok(_) => return Err(format!("expected {func} to revert, but it succeeded")),
+            Err(CallError::Revert(_, data)) => data,
+            Err(err) => return Err(format!("expected {func} to revert, but it failed: {err}")),
+        };
+        if data.len() < 4 || data[..4] != selector {
+            let expected = Bytes::from(selector.to_vec());
+            return Err(format!(
+                "expected {func} to revert with selector {expected}, but got {data}"
+            ))
+        }
+
+        let error = self
+            .abi
+            .errors()
+            .chain(self.errors.iter().flat_map(|errors| errors.errors()))
+            .find(|error| error.signature()[..4] == selector);
+        match error {
+            Some(error) => error.decode(&data[4..]).map_err(|err| err.to_string()),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Sends the raw calldata to the contract, bypassing ABI encoding, and returns the exact
+    /// return data of the call. Useful for probing fallback handling and proxy dispatch.
+    pub async fn call_raw(
+        &mut self,
+        data: Bytes,
+        value: U256,
+    ) -> Result<CallResult<Bytes>, CallError> {
+        let caller = self.sender();
+        let label = match data.get(..4) {
+            Some(selector) => format!("0x{}", hex::encode(selector)),
+            None => "fallback".to_string(),
+        };
+        self.transact(&label, data, CallOpts { value, ..CallOpts::new(caller) })
+    }
+
+    /// Sends `value` wei to the contract with empty calldata, triggering its `receive` or
+    /// `fallback` function.
+    pub async fn send_eth(&mut self, value: U256) -> Result<CallResult<()>, CallError> {
+        let caller = self.sender();
+        let call =
+            self.transact("receive", Bytes::new(), CallOpts { value, ..CallOpts::new(caller) })?;
+
+        Ok(call.with_result(()))
+    }
+
+    /// Calls the `fallback` function of the contract with the given calldata and value,
+    /// returning the exact return data of the call.
+    pub async fn call_fallback(
+        &mut self,
+        data: Bytes,
+        value: U256,
+    ) -> Result<CallResult<Bytes>, CallError> {
+        let caller = self.sender();
+        self.transact("fallback", data, CallOpts { value, ..CallOpts::new(caller) })
+    }
+
+    /// Sends a transaction calling the function with `value` wei, returning its receipt instead
+    /// of the decoded output. Reverting transactions aren't errors, their receipt has a failed
+    /// status like on chain. Successful transactions fail with [`CallError::MissingEvent`] if
+    /// they didn't emit the events expected with [`Contract::expect_emit`].
+    pub async fn send<T: Tokenize>(
+        &mut self,
+        func: &str,
+        args: T,
+        value: U256,
+    ) -> Result<TxReceipt, CallError> {
+        let function = find_function(&self.abi, func)?;
+        let calldata = function.encode_input(&args.into_tokens()).map_err(decode_err)?;
+
+        let caller = self.sender();
+        let opts = CallOpts { value, ..CallOpts::new(caller) };
+        let expected_emits = std::mem::take(&mut self.expected_emits);
+        let (call, _, accesses) = self.transact_raw(func, calldata.into(), opts)?;
+        // logs of failed transactions are discarded, like on chain.
+        let logs = if call.reverted { vec![] } else { call.logs };
+        if !call.reverted {
+            check_emits(expected_emits, &logs)?;
+        }
+
+        Ok(TxReceipt {
+            status: !call.reverted,
+            gas_used: call.gas_used,
+            logs,
+            return_data: call.result.into(),
+            accesses,
+        })
+    }
+
+    /// Sets `block.timestamp` for all subsequent calls, like `vm.warp`.
+    pub fn warp(&mut self, timestamp: u64) -> Result<(), EvmError> {
+        self.cheatcode("warp(uint256)", (U256::from(timestamp),), true)?;
+        // keep the environment in sync, subsequent transactions are built from it.
+        self.executor().env.block.timestamp = u256_to_ru256(timestamp.into());
+        Ok(())
+    }
+
+    /// Sets `block.number` for all subsequent calls, like `vm.roll`.
+    pub fn roll(&mut self, block: u64) -> Result<(), EvmError> {
+        self.cheatcode("roll(uint256)", (U256::from(block),), true)?;
+        self.executor().env.block.number = u256_to_ru256(block.into());
+        Ok(())
+    }
+
+    /// Advances the chain by `blocks` blocks, moving `block.timestamp` forward by
+    /// `seconds_per_block` for every block.
+    pub fn advance(&mut self, blocks: u64, seconds_per_block: u64) -> Result<(), EvmError> {
+        let (number, timestamp) = {
+            let executor = self.executor();
+            (executor.env.block.number, executor.env.block.timestamp)
+        };
+        self.roll(ru256_to_u256(number).as_u64() + blocks)?;
+        self.warp(ru256_to_u256(timestamp).as_u64() + blocks * seconds_per_block)
+    }
+
+    /// Sets `block.basefee` for all subsequent calls, like `vm.fee`. The gas price of subsequent
+    /// calls is raised to the base fee if it's lower.
+    pub fn set_base_fee(&mut self, base_fee: U256) -> Result<(), EvmError> {
+        self.cheatcode("fee(uint256)", (base_fee,), true)?;
+        let mut executor = self.executor();
+        let env = &mut executor.env;
+        env.block.basefee = u256_to_ru256(base_fee);
+        env.tx.gas_price = env.tx.gas_price.max(env.block.basefee);
+        Ok(())
+    }
+
+    /// Sets `tx.gasprice` for all subsequent calls, like `vm.txGasPrice`. The gas price is
+    /// raised to the base fee if it's lower, since such transactions are rejected.
+    pub fn set_gas_price(&mut self, gas_price: U256) -> Result<(), EvmError> {
+        self.cheatcode("txGasPrice(uint256)", (gas_price,), true)?;
+        let mut executor = self.executor();
+        let env = &mut executor.env;
+        env.tx.gas_price = u256_to_ru256(gas_price).max(env.block.basefee);
+        Ok(())
+    }
+
+    /// Sets `block.prevrandao` for all subsequent calls, like `vm.prevrandao`. Under specs
+    /// before the merge it sets `block.difficulty` instead, like `vm.difficulty`.
+    pub fn set_prevrandao(&mut self, prevrandao: H256) -> Result<(), EvmError> {
+        if self.executor().env.cfg.spec_id >= SpecId::MERGE {
+            self.cheatcode("prevrandao(bytes32)", (prevrandao,), true)?;
+        } else {
+            let difficulty = U256::from_big_endian(prevrandao.as_bytes());
+            self.cheatcode("difficulty(uint256)", (difficulty,), true)?;
+        }
+        set_prevrandao(&mut self.executor().env.block, prevrandao);
+        Ok(())
+    }
+
+    /// Sets `block.chainid` for all subsequent calls, like `vm.chainId`.
+    pub fn set_chain_id(&mut self, chain_id: u64) -> Result<(), EvmError> {
+        self.cheatcode("chainId(uint256)", (U256::from(chain_id),), true)?;
+        self.executor().env.cfg.chain_id = u256_to_ru256(chain_id.into());
+        Ok(())
+    }
+
+    /// Sets `block.coinbase` for all subsequent calls, like `vm.coinbase`.
+    pub fn set_coinbase(&mut self, coinbase: Address) -> Result<(), EvmError> {
+        self.cheatcode("coinbase(address)", (coinbase,), true)?;
+        self.executor().env.block.coinbase = h160_to_b160(coinbase);
+        Ok(())
+    }
+
+    /// Takes a snapshot of the current state, returning an id that can be passed to
+    /// [`Contract::revert_to`], like `vm.snapshot`.
+    pub fn snapshot(&mut self) -> Result<U256, EvmError> {
+        let output = self.cheatcode("snapshot()", (), true)?;
+        let id = U256::decode(output).map_err(evm_err)?;
+        let block = self.executor().env.block.clone();
+        self.snapshots.insert(id, (self.event_logs.len(), block));
+        Ok(id)
+    }
+
+    /// Reverts the state to the snapshot with the given id, like `vm.revertTo`. The snapshot is
+    /// consumed in the process, returns false if the id is invalid or was already consumed.
+    /// Reverting to a snapshot taken through this handle also restores the block environment,
+    /// e.g. the timestamp set by [`Contract::warp`], and drops the events emitted since from
+    /// [`Contract::all_events`].
+    pub fn revert_to(&mut self, id: U256) -> Result<bool, EvmError> {
+        let output = self.cheatcode("revertTo(uint256)", (id,), true)?;
+        let reverted = bool::decode(output).map_err(evm_err)?;
+        // reverting also discards the snapshots taken after this one.
+        let mut discarded = self.snapshots.split_off(&id);
+        if let Some((events, block)) = discarded.remove(&id).filter(|_| reverted) {
+            self.event_logs.truncate(events);
+            self.executor().env.block = block;
+        }
+        Ok(reverted)
+    }
+
+    /// Reads the storage slot of the contract, like `vm.load`. The read doesn't modify any state.
+    pub fn load(&mut self, slot: H256) -> Result<H256, EvmError> {
+        self.load_at(self.address, slot)
+    }
+
+    /// Writes `value` to the storage slot of the contract, like `vm.store`. The write persists
+    /// across subsequent calls.
+    pub fn store(&mut self, slot: H256, value: H256) -> Result<(), EvmError> {
+        self.store_at(self.address, slot, value)
+    }
+
+    /// Sets the ERC20 `token` balance of `to` to `amount` by writing to the token's balances
+    /// mapping directly, like forge-std's `deal`. When `adjust_total_supply` is set the total
+    /// supply is adjusted by the difference.
+    pub fn deal_token(
+        &mut self,
+        token: Address,
+        to: Address,
+        amount: U256,
+        adjust_total_supply: bool,
+    ) -> Result<(), EvmError> {
+        let balance_slot = self.find_slot(token, "balanceOf(address)", (to,))?;
+        let previous = U256::from_big_endian(self.load_at(token, balance_slot)?.as_bytes());
+        self.store_at(token, balance_slot, word(amount))?;
+
+        if adjust_total_supply {
+            let supply_slot = self.find_slot(token, "totalSupply()", ())?;
+            let supply = U256::from_big_endian(self.load_at(token, supply_slot)?.as_bytes());
+            let supply = if amount > previous {
+                supply.checked_add(amount - previous)
+            } else {
+                supply.checked_sub(previous - amount)
+            };
+            let supply = supply.ok_or_else(|| {
+                EvmError::Eyre(eyre::eyre!("total supply of {token:?} out of bounds"))
+            })?;
+            self.store_at(token, supply_slot, word(supply))?;
+        }
+
+        Ok(())
+    }
+
+    /// Places the runtime `code` at `addr` without running a constructor, like `vm.etch`. Use
+    /// [`Runner::attach`](crate::Runner::attach) to call the etched contract.
+    pub fn etch(&mut self, addr: Address, code: Bytes) -> Result<(), EvmError> {
+        self.cheatcode("etch(address,bytes)", (addr, code), true)?;
+        Ok(())
+    }
+
+    /// Makes calls to `target` return `return_data` instead of executing, like `vm.mockCall`.
+    /// Calls match if their calldata starts with `calldata`, so passing only a selector mocks
+    /// the function regardless of its arguments.
+    pub fn mock_call(
+        &mut self,
+        target: Address,
+        calldata: Bytes,
+        return_data: Bytes,
+    ) -> Result<(), EvmError> {
+        self.cheatcode("mockCall(address,bytes,bytes)", (target, calldata, return_data), true)?;
+        Ok(())
+    }
+
+    /// Names `addr` in the rendered call traces, like `vm.label`.
+    pub fn label(&mut self, addr: Address, name: &str) -> Result<(), EvmError> {
+        self.cheatcode("label(address,string)", (addr, name.to_string()), true)?;
+        self.labels.insert(addr, name.to_string());
+        Ok(())
+    }
+
+    /// Reverts the state to right after the contract was deployed, along with the block
+    /// environment and the events returned by [`Contract::all_events`]. Since contracts share
+    /// their state, this also undoes the deployments and calls of contracts deployed afterwards.
+    /// Pranks, labels and mocked calls are kept, as are the gas report and coverage collected
+    /// since.
+    pub fn reset(&mut self) -> Result<(), EvmError> {
+        if !self.revert_to(self.setup_snapshot)? {
+            return Err(EvmError::Eyre(eyre::eyre!("the setup snapshot of {} is gone", self.name)))
+        }
+        self.event_logs = self.setup.logs.clone();
+        // reverting consumes the snapshot, so take it again for the next reset.
+        self.setup_snapshot = self.snapshot()?;
+        Ok(())
+    }
+
+    /// Makes `who` the sender of the next call, like `vm.prank`. Calls that take an explicit
+    /// caller are unaffected.
+    pub fn prank(&mut self, who: Address) {
+        self.prank = Some(who);
+    }
+
+    /// Makes `who` the sender of every call until [`Contract::stop_prank`], like
+    /// `vm.startPrank`. Calls that take an explicit caller are unaffected.
+    pub fn start_prank(&mut self, who: Address) {
+        self.persistent_prank = Some(who);
+    }
+
+    /// Stops a prank started with [`Contract::start_prank`], like `vm.stopPrank`.
+    pub fn stop_prank(&mut self) {
+        self.persistent_prank = None;
+    }
+
+    /// The ether balance of `addr`.
+    pub fn balance_of(&self, addr: Address) -> Result<U256, EvmError> {
+        self.executor().get_balance(addr).map_err(evm_err)
+    }
+
+    /// The nonce of `addr`.
+    pub fn nonce_of(&self, addr: Address) -> Result<u64, EvmError> {
+        Ok(self.account_info(addr)?.nonce)
+    }
+
+    /// The runtime code deployed at `addr`, empty if there is none.
+    pub fn code_at(&self, addr: Address) -> Result<Bytes, EvmError> {
+        let info = self.account_info(addr)?;
+        let code = match info.code {
+            Some(code) => code,
+            None => self.executor().backend.code_by_hash(info.code_hash).map_err(evm_err)?,
+        };
+        Ok(code.original_bytes().into())
+    }
+
+    /// Whether the contract's own code has been cleared, e.g. by `selfdestruct`. The code is
+    /// only cleared once the self-destructing call is committed, and the balance forwarded to
+    /// the beneficiary can be checked with [`Contract::balance_of`]:
+    ///
+    /// ```ignore
+    /// contract.call::<_, ()>("destroy", (beneficiary,)).await?;
+    /// assert!(contract.is_destroyed()?);
+    /// assert_eq!(contract.balance_of(beneficiary)?, balance);
+    /// ```
+    ///
+    /// The EVM doesn't implement EIP-6780, so `selfdestruct` clears the code under every spec,
+    /// including Cancun where on chain it only does so in the transaction that created the
+    /// contract.
+    pub fn is_destroyed(&self) -> Result<bool, EvmError> {
+        Ok(self.code_at(self.address)?.is_empty())
+    }
+
+    /// The size in bytes of the contract's deployed runtime code, which EIP-170 limits to
+    /// 24576 bytes.
+    pub fn code_size(&self) -> Result<usize, EvmError> {
+        Ok(self.code_at(self.address)?.len())
+    }
+
+    /// Reads the value of the immutable variable `name` from the deployed code, for immutables
+    /// without a getter.
+    pub fn read_immutable(&self, name: &str) -> Result<Token, EvmError> {
+        let (kind, offset) = self
+            .immutables
+            .get(name)
+            .ok_or_else(|| EvmError::Eyre(eyre::eyre!("immutable {name} not found")))?;
+        let code = self.code_at(self.address)?;
+        let value = code
+            .get(*offset..offset + 32)
+            .ok_or_else(|| EvmError::Eyre(eyre::eyre!("immutable {name} is out of bounds")))?;
+        let mut tokens = abi::decode(&[kind.clone()], value).map_err(evm_err)?;
+        Ok(tokens.remove(0))
+    }
+
+    /// Starts recording the storage slots read and written by every subsequent call made through
+    /// this handle, see [`CallResult::accesses`].
+    pub fn record_accesses(&mut self) {
+        self.recording_accesses = true;
+    }
+
+    /// Stops recording storage accesses.
+    pub fn stop_record_accesses(&mut self) {
+        self.recording_accesses = false;
+    }
+
+    /// Starts diffing the state changed by every subsequent call made through this handle, see
+    /// [`CallResult::state_diff`]. Diffing a committed call copies the backend before the call
+    /// runs, which is slow on large or forked states.
+    pub fn record_state_diffs(&mut self) {
+        self.recording_state_diffs = true;
+    }
+
+    /// Stops diffing the state changed by calls.
+    pub fn stop_record_state_diffs(&mut self) {
+        self.recording_state_diffs = false;
+    }
+
+    /// Decodes all the `E` events emitted by the contract since it was deployed, across all
+    /// committed calls made through this handle that didn't revert, or since the last
+    /// [`Contract::clear_events`].
+    pub fn all_events<E: EthEvent>(&self) -> Vec<E> {
+        decode_events(&self.event_logs)
+            .into_iter()
+            .filter(|(address, _)| *address == self.address)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    /// Decodes the logs emitted by any contract against the events in this contract's ABI, e.g.
+    /// `call.logs`. Logs that don't match any of its events, including anonymous ones, are
+    /// skipped. Indexed `string`, `bytes`, array and struct parameters are only stored as the
+    /// keccak256 hash of their value, so they're decoded as that hash in a
+    /// [`Token::FixedBytes`].
+    pub fn decoded_events(&self, logs: &[Log]) -> Vec<DecodedEvent> {
+        decode_abi_events(&self.abi, logs)
+    }
+
+    /// Expects the next call to emit `event`, like `vm.expectEmit`. The call fails with
+    /// [`CallError::MissingEvent`] if it succeeds without emitting a log matching every field
+    /// of the event, its effects are still committed. Events expected before the same call must
+    /// be emitted in the order they were expected.
+    pub fn expect_emit<E>(&mut self, event: E) -> Result<(), CallError>
+    where
+        E: EthEvent + Tokenizable + Clone + Send + Sync + 'static,
+    {
+        self.expect_emit_with(event, [true; 3], true)
+    }
+
+    /// Like [`Contract::expect_emit`], but only matching the indexed fields whose flag in
+    /// `topics` is set and the other fields if `data` is set, like the flags of `vm.expectEmit`.
+    /// Skipping fields requires the event to be in the contract ABI, to tell which are indexed.
+    pub fn expect_emit_with<E>(
+        &mut self,
+        event: E,
+        topics: [bool; 3],
+        data: bool,
+    ) -> Result<(), CallError>
+    where
+        E: EthEvent + Tokenizable + Clone + Send + Sync + 'static,
+    {
+        let indexed = self
+            .abi
+            .events()
+            .find(|abi| abi.signature() == E::signature())
+            .map(|abi| abi.inputs.iter().map(|param| param.indexed).collect::<Vec<_>>());
+        if indexed.is_none() && (topics != [true; 3] || !data) {
+            return Err(decode_err(format!(
+                "event {} is not in the ABI of {}",
+                E::name(),
+                self.name
+            )))
+        }
+
+        let matches = move |log: &Log| {
+            let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+            let Ok(emitted) = E::decode_log(&raw) else { return false };
+            let (emitted, expected) =
+                (fields(emitted.into_token()), fields(event.clone().into_token()));
+            let mut topic = 0;
+            emitted.iter().zip(&expected).enumerate().all(|(i, (emitted, expected))| {
+                let checked = match indexed.as_ref().map(|indexed| indexed.get(i) == Some(&true)) {
+                    Some(true) => {
+                        topic += 1;
+                        topics.get(topic - 1).copied().unwrap_or(true)
+                    },
+                    Some(false) => data,
+                    None => true,
+                };
+                !checked || emitted == expected
+            })
+        };
+        self.expected_emits
+            .push(ExpectedEmit { name: E::name().into_owned(), matches: Box::new(matches) });
+        Ok(())
+    }
+
+    /// Clears the events accumulated for [`Contract::all_events`].
+    pub fn clear_events(&mut self) {
+        self.event_logs.clear();
+    }
+
+    /// Overwrites the ether balance of `who`, like `vm.deal`.
+    pub fn set_balance(&mut self, who: Address, amount: U256) -> Result<(), EvmError> {
+        self.executor().set_balance(who, amount).map_err(evm_err)?;
+        Ok(())
+    }
+
+    /// The line coverage of every call made to the contract so far, including its deployment.
+    /// Empty unless enabled with [`RunnerBuilder::coverage`](crate::RunnerBuilder::coverage).
+    pub fn coverage(&self) -> CoverageReport {
+        self.coverage.clone().unwrap_or_default()
+    }
+
+    /// The call trace of the most recent call, including calls that reverted. Each frame records
+    /// its depth, target, calldata and gas used, and the trace renders as a forge style tree
+    /// with [`ToString`]. Calls to functions of project contracts are rendered by name with their
+    /// decoded arguments, any others by selector, and addresses named with [`Contract::label`]
+    /// are rendered by their label.
+    pub fn last_trace(&self) -> Option<&CallTraceArena> {
+        self.last_trace.as_ref()
+    }
+
+    /// Releases the contract's reference to the executor it shares with its runner, see
+    /// [`Runner::close`](crate::Runner::close). The executor is only dropped once no runner or
+    /// contract references it anymore.
+    pub fn close(self) {
+        drop(self.executor);
+    }
+
+    /// The account at `address`, or an empty account if it doesn't exist.
+    fn account_info(&self, address: Address) -> Result<AccountInfo, EvmError> {
+        let account = self.executor().backend.basic(h160_to_b160(address)).map_err(evm_err)?;
+        Ok(account.unwrap_or_default())
+    }
+
+    /// The sender of the next call, consuming a pending [`Contract::prank`].
+    fn sender(&mut self) -> Address {
+        self.prank.take().or(self.persistent_prank).unwrap_or(self.sender)
+    }
+
+    fn dispatch<T, R>(
+        &mut self,
+        func: &str,
+        args: T,
+        opts: CallOpts,
+    ) -> Result<CallResult<R>, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let function = find_function(&self.abi, func)?;
+        if !opts.value.is_zero() && function.state_mutability != StateMutability::Payable {
+            return Err(EvmError::Eyre(eyre::eyre!("function {func} is not payable")).into())
+        }
+
+        let calldata = function.encode_input(&args.into_tokens()).map_err(decode_err)?;
+        let call = self.transact(func, calldata.into(), opts)?;
+
+        let tokens = function.decode_output(&call.result).map_err(decode_err)?;
+        let result = R::from_tokens(tokens).map_err(decode_err)?;
+
+        Ok(call.with_result(result))
+    }
+
+    /// Sends the calldata to the contract, turning reverts, halts and running out of gas into
+    /// errors. `label` identifies the call in the logs and the gas report.
+    fn transact(
+        &mut self,
+        label: &str,
+        calldata: Bytes,
+        opts: CallOpts,
+    ) -> Result<CallResult<Bytes>, CallError> {
+        // the transaction is rejected before executing if it can't pay for its calldata.
+        if matches!(opts.gas_limit, Some(gas_limit) if gas_limit < intrinsic_gas(&calldata)) {
+            return Err(CallError::OutOfGas)
+        }
+        // expectations only apply to the next call, whether it succeeds or not.
+        let expected_emits = std::mem::take(&mut self.expected_emits);
+        let (call, state_diff, accesses) = self.transact_raw(label, calldata, opts)?;
+
+        if matches!(
+            call.exit_reason,
+            InstructionResult::OutOfGas |
+                InstructionResult::MemoryOOG |
+                InstructionResult::MemoryLimitOOG |
+                InstructionResult::PrecompileOOG |
+                InstructionResult::InvalidOperandOOG
+        ) {
+            return Err(CallError::OutOfGas)
+        }
+
+        if call.reverted {
+            if call.exit_reason != InstructionResult::Revert {
+                return Err(CallError::Halt(call.exit_reason))
+            }
+            let reason = RevertReason::decode(&call.result, &self.abi, self.errors.as_ref());
+            return Err(CallError::Revert(reason, call.result.into()))
+        }
+
+        check_emits(expected_emits, &call.logs)?;
+
+        let output = Bytes::from(call.result);
+        Ok(CallResult {
+            result: output.clone(),
+            raw_output: output,
+            address: self.address,
+            logs: call.logs,
+            gas_used: call.gas_used,
+            state_diff,
+            accesses,
+            trace: call.traces,
+        })
+    }
+
+    /// Sends the calldata to the contract, recording the trace, gas and coverage of the call
+    /// whether it succeeds or not, unless `opts.record` is unset. The state diff and storage
+    /// accesses of the call are returned along with it if they're being recorded.
+    fn transact_raw(
+        &mut self,
+        label: &str,
+        calldata: Bytes,
+        opts: CallOpts,
+    ) -> Result<(RawCallResult, StateDiff, AccountAccesses), EvmError> {
+        let CallOpts { caller, origin, value, commit, record, gas_limit, access_list } = opts;
+        if self.recording_accesses {
+            // starting a new recording clears the accesses of the previous call.
+            self.cheatcode("record()", (), true)?;
+        }
+        if commit && self.block_time > 0 {
+            if self.roll_blocks {
+                self.advance(1, self.block_time)?;
+            } else {
+                let timestamp = ru256_to_u256(self.executor().env.block.timestamp).as_u64();
+                self.warp(timestamp + self.block_time)?;
+            }
+        }
+        let origin = origin.or(self.origin).filter(|origin| *origin != caller);
+        let (from, to, calldata) = match origin {
+            // the sender of a transaction is also its origin, so relay the call through a
+            // contract that pranks the caller.
+            Some(origin) => {
+                // etching on every call would commit even for static calls.
+                if self.account_info(ORIGIN_FORWARDER)?.code_hash == KECCAK_EMPTY {
+                    self.etch(ORIGIN_FORWARDER, origin_forwarder())?;
+                }
+                let calldata =
+                    [caller.as_bytes(), self.address.as_bytes(), calldata.as_ref()].concat();
+                (origin, ORIGIN_FORWARDER, Bytes::from(calldata))
+            },
+            None => (caller, self.address, calldata),
+        };
+        let mut executor = self.executor.lock().unwrap();
+
+        // make sure the sender can pay for gas and afford the value being sent.
+        let balance = executor.get_balance(from).map_err(evm_err)?;
+        if commit && (balance.is_zero() || balance < value) {
+            executor.set_balance(from, value.max(self.initial_balance)).map_err(evm_err)?;
+        }
+
+        if let Some(gas_limit) = gas_limit {
+            executor.set_gas_limit(gas_limit.into());
+        }
+        executor.env.tx.access_list = access_list
+            .0
+            .iter()
+            .map(|item| {
+                let keys = item.storage_keys.iter();
+                let slots = keys.map(|key| u256_to_ru256(U256::from_big_endian(key.as_bytes())));
+                (h160_to_b160(item.address), slots.collect())
+            })
+            .collect();
+        // committed changes overwrite the state the call ran against, keep it for the diff.
+        let pre = (commit && self.recording_state_diffs).then(|| executor.backend.clone());
+        let call = if commit {
+            executor.call_raw_committing(from, to, calldata.0, value)
+        } else {
+            executor.call_raw(from, to, calldata.0, value)
+        };
+        if gas_limit.is_some() {
+            executor.set_gas_limit(self.gas_limit);
+        }
+        executor.env.tx.access_list.clear();
+        let mut call = call.map_err(evm_err)?;
+        if origin.is_some() && call.reverted {
+            // the forwarder reverts whenever the relayed call fails, take how it failed from
+            // the trace of the relayed call instead.
+            let relayed = call.traces.as_ref().and_then(|trace| {
+                let forwarder = trace.arena.first()?;
+                let mut children = forwarder.children.iter().map(|child| &trace.arena[*child]);
+                children.find(|node| node.trace.address == self.address)
+            });
+            if let Some(node) = relayed.filter(|node| !node.trace.success) {
+                call.exit_reason = node.trace.status;
+            }
+        }
+        let state_diff = call
+            .state_changeset
+            .as_ref()
+            .filter(|_| self.recording_state_diffs)
+            .map(|changes| StateDiff::new(pre.as_ref().unwrap_or(&executor.backend), changes))
+            .unwrap_or_default();
+        drop(executor);
+        if let Some(trace) = &mut call.traces {
+            let labels = call.labels.iter().chain(&self.labels);
+            for (address, label) in labels {
+                for node in trace.arena.iter_mut().filter(|node| node.trace.address == *address) {
+                    node.trace.label = Some(label.clone());
+                }
+            }
+            for node in trace.arena.iter_mut() {
+                decode_call(&mut node.trace, &self.functions);
+            }
+        }
+
+        let mut accesses = AccountAccesses::default();
+        if self.recording_accesses {
+            let touched = call.state_changeset.iter().flat_map(|changes| changes.keys());
+            for address in touched.map(|address| b160_to_h160(*address)).collect::<Vec<_>>() {
+                let (reads, writes) = self.storage_accesses(address)?;
+                accesses.accounts.insert(address, StorageAccesses { reads, writes });
+            }
+        }
+
+        if commit && !call.reverted {
+            self.event_logs.extend(call.logs.iter().cloned());
+        }
+        if !record {
+            return Ok((call, state_diff, accesses))
+        }
+        log_call(self.verbosity, label, &call, call.traces.as_ref());
+        self.last_trace = call.traces.clone();
+        if let Some(report) = &self.gas_report {
+            report.lock().unwrap().record(&self.name, label, call.gas_used);
+        }
+        if let (Some(report), Some(hits)) = (&mut self.coverage, &call.coverage) {
+            report.merge(hits);
+        }
+
+        Ok((call, state_diff, accesses))
+    }
+
+    /// Invokes the cheatcode with the given signature. When `commit` is set its effects persist
+    /// across subsequent calls, otherwise the state is left untouched.
+    fn cheatcode<T: Tokenize>(
+        &mut self,
+        signature: &str,
+        args: T,
+        commit: bool,
+    ) -> Result<Bytes, EvmError> {
+        self.call_at(CHEATCODE_ADDRESS, signature, args, commit)
+    }
+
+    /// Calls the function with the given signature on `to` from [`CHEATCODE_CALLER`], returning
+    /// the raw output. Reverts are returned as errors.
+    fn call_at<T: Tokenize>(
+        &mut self,
+        to: Address,
+        signature: &str,
+        args: T,
+        commit: bool,
+    ) -> Result<Bytes, EvmError> {
+        Ok(self.raw_call_at(to, signature, args, commit)?.result.into())
+    }
+
+    /// Like [`Contract::call_at`], but returning the whole result of the call.
+    fn raw_call_at<T: Tokenize>(
+        &mut self,
+        to: Address,
+        signature: &str,
+        args: T,
+        commit: bool,
+    ) -> Result<RawCallResult, EvmError> {
+        let calldata = [&id(signature)[..], &abi::encode(&args.into_tokens())].concat();
+        let initial_balance = self.initial_balance;
+        let mut executor = self.executor();
+        if executor.get_balance(CHEATCODE_CALLER).map_err(evm_err)?.is_zero() {
+            executor.set_balance(CHEATCODE_CALLER, initial_balance).map_err(evm_err)?;
+        }
+        let result = if commit {
+            executor.call_raw_committing(CHEATCODE_CALLER, to, calldata.into(), U256::zero())
+        } else {
+            executor.call_raw(CHEATCODE_CALLER, to, calldata.into(), U256::zero())
+        }
+        .map_err(evm_err)?;
+
+        if result.reverted {
+            let reason = decode_revert(&result.result, None, Some(result.exit_reason))
+                .unwrap_or_else(|_| "unknown reason".to_string());
+            return Err(EvmError::Eyre(eyre::eyre!("call to {signature} failed: {reason}")))
+        }
+
+        Ok(result)
+    }
+
+    /// Finds the storage slot of `token` read by the call to `signature` whose value the call
+    /// returns, like forge-std's `stdstore`.
+    fn find_slot<T: Tokenize + Clone>(
+        &mut self,
+        token: Address,
+        signature: &str,
+        args: T,
+    ) -> Result<H256, EvmError> {
+        // the changes of a call include the slots it loaded, read them from there rather than
+        // with `record()`, which would keep recording after this and clear a running recording.
+        let call = self.raw_call_at(token, signature, args.clone(), false)?;
+        let mut reads = call
+            .state_changeset
+            .as_ref()
+            .and_then(|changes| changes.get(&h160_to_b160(token)))
+            .map(|account| account.storage.keys().map(|slot| word(ru256_to_u256(*slot))).collect())
+            .unwrap_or_default();
+        reads.sort();
+
+        // the slot is found once writing to it changes the returned value.
+        let probe = H256::repeat_byte(0x13);
+        for slot in reads {
+            let original = self.load_at(token, slot)?;
+            self.store_at(token, slot, probe)?;
+            let value = self.call_at(token, signature, args.clone(), false)?;
+            self.store_at(token, slot, original)?;
+            if value.as_ref() == probe.as_bytes() {
+                return Ok(slot)
+            }
+        }
+
+        Err(EvmError::Eyre(eyre::eyre!("no storage slot found for {signature} on {token:?}")))
+    }
+
+    /// The storage slots of `address` read and written since recording started, like
+    /// `vm.accesses`.
+    fn storage_accesses(&mut self, address: Address) -> Result<(Vec<H256>, Vec<H256>), EvmError> {
+        let output = self.cheatcode("accesses(address)", (address,), true)?;
+        let kinds = vec![ParamType::Array(Box::new(ParamType::FixedBytes(32))); 2];
+        <(Vec<H256>, Vec<H256>)>::from_tokens(abi::decode(&kinds, &output).map_err(evm_err)?)
+            .map_err(evm_err)
+    }
+
+    /// Reads the storage slot of the contract at `address`.
+    fn load_at(&mut self, address: Address, slot: H256) -> Result<H256, EvmError> {
+        let output = self.cheatcode("load(address,bytes32)", (address, slot), false)?;
+        H256::decode(output).map_err(evm_err)
+    }
+
+    /// Writes `value` to the storage slot of the contract at `address`.
+    fn store_at(&mut self, address: Address, slot: H256, value: H256) -> Result<(), EvmError> {
+        self.cheatcode("store(address,bytes32,bytes32)", (address, slot, value), true)?;
+        Ok(())
+    }
+}
+
+/// The sender of cheatcode calls. Committed transactions bump the nonce of their sender, so
+/// sending them from the default sender would shift the addresses of its deployments.
+const CHEATCODE_CALLER: Address = H160([
+    0x63, 0x68, 0x65, 0x61, 0x74, 0x63, 0x6f, 0x64, 0x65, 0x2d, 0x63, 0x61, 0x6c, 0x6c, 0x65, 0x72,
+    0x00, 0x00, 0x00, 0x00,
+]);
+
+/// The address calls with an origin other than their sender are relayed through.
+const ORIGIN_FORWARDER: Address = H160([
+    0x6f, 0x72, 0x69, 0x67, 0x69, 0x6e, 0x2d, 0x66, 0x6f, 0x72, 0x77, 0x61, 0x72, 0x64, 0x65, 0x72,
+    0x00, 0x00, 0x00, 0x00,
+]);
+
+/// Runtime code of a forwarder taking `caller ++ target ++ calldata`, which pranks `caller` and
+/// calls `target` with the calldata and value, bubbling up the return or revert data.
+fn origin_forwarder() -> Bytes {
+    let mut code = vec![0x63];
+    code.extend_from_slice(&id("prank(address)"));
+    code.extend_from_slice(&[
+        0x60, 0xe0, 0x1b, 0x60, 0x00, 0x52, // mstore(0, selector << 224)
+        0x60, 0x00, 0x35, 0x60, 0x60, 0x1c, 0x60, 0x04, 0x52, // mstore(4, caller)
+        0x60, 0x00, 0x60, 0x00, 0x60, 0x24, 0x60, 0x00, 0x60, 0x00,
+        0x73, // call the cheatcode
+    ]);
+    code.extend_from_slice(CHEATCODE_ADDRESS.as_bytes());
+    code.extend_from_slice(&[
+        0x5a, 0xf1, 0x50, // with all gas, ignoring the result
+        0x60, 0x28, 0x36, 0x03, 0x60, 0x28, 0x60, 0x00, 0x37, // copy the calldata to memory
+        0x60, 0x00, 0x60, 0x00, 0x60, 0x28, 0x36, 0x03, 0x60, 0x00,
+        0x34, // call with the value
+        0x60, 0x14, 0x35, 0x60, 0x60, 0x1c, 0x5a, 0xf1, // the target with all gas
+        0x3d, 0x60, 0x00, 0x60, 0x00, 0x3e, // copy the return data to memory
+        0x60, 0x5f, 0x57, 0x3d, 0x60, 0x00, 0xfd, // revert with it if the call failed
+        0x5b, 0x3d, 0x60, 0x00, 0xf3, // return it otherwise
+    ]);
+    code.into()
+}
+
+/// Resolves the immutable references of the named contract to the immutables declared in the
+/// sources, including the ones inherited from other files.
+fn immutables(output: &ProjectCompileOutput, name: &str) -> BTreeMap<String, (ParamType, usize)> {
+    let Some(artifact) = output
+        .artifact_ids()
+        .find_map(|(id, artifact)| (id.name == name).then_some(artifact))
+    else {
+        return Default::default()
+    };
+    let Some(references) = artifact
+        .deployed_bytecode
+        .as_ref()
+        .map(|deployed| &deployed.immutable_references)
+    else {
+        return Default::default()
+    };
+
+    // references are keyed by the AST id of the variable declaration.
+    let mut declarations = BTreeMap::new();
+    let asts = std::iter::once(artifact).chain(output.artifacts().map(|(_, artifact)| artifact));
+    for ast in asts.filter_map(|artifact| artifact.ast.as_ref()) {
+        if references.keys().all(|id| declarations.contains_key(id)) {
+            break
+        }
+        collect_immutables(&ast.nodes, &mut declarations);
+    }
+
+    references
+        .iter()
+        .filter_map(|(id, offsets)| {
+            let (name, kind) = declarations.get(id)?.clone();
+            Some((name, (kind, offsets.first()?.start as usize)))
+        })
+        .collect()
+}
+
+/// Collects the name and type of every immutable variable declared in the AST nodes, by id.
+fn collect_immutables(nodes: &[Node], declarations: &mut BTreeMap<String, (String, ParamType)>) {
+    for node in nodes {
+        let immutable = node.node_type == NodeType::VariableDeclaration &&
+            node.other.get("mutability").and_then(|value| value.as_str()) == Some("immutable");
+        if let Some(id) = node.id.filter(|_| immutable) {
+            let name = node.other.get("name").and_then(|value| value.as_str());
+            let kind = node
+                .other
+                .get("typeDescriptions")
+                .and_then(|types| types.get("typeString"))
+                .and_then(|value| value.as_str())
+                .and_then(param_type);
+            if let (Some(name), Some(kind)) = (name, kind) {
+                declarations.insert(id.to_string(), (name.to_string(), kind));
+            }
+        }
+        collect_immutables(&node.nodes, declarations);
+    }
+}
+
+/// Parses the ABI type of a solidity type string, mapping contracts and enums to their encoding.
+fn param_type(type_string: &str) -> Option<ParamType> {
+    if type_string.starts_with("contract ") || type_string.starts_with("address") {
+        return Some(ParamType::Address)
+    }
+    if type_string.starts_with("enum ") {
+        return Some(ParamType::Uint(8))
+    }
+    abi::param_type::Reader::read(type_string).ok()
+}
+
+/// Looks up a function either by its name or by its full signature. Looking up an overloaded
+/// function by name fails since the overload to call is ambiguous.
+fn find_function(abi: &Abi, func: &str) -> Result<Function, EvmError> {
+    if func.contains('(') {
+        let selector = id(func);
+        return abi
+            .functions()
+            .find(|function| function.short_signature() == selector)
+            .cloned()
+            .ok_or_else(|| EvmError::Eyre(eyre::eyre!("function {func} not found")))
+    }
+
+    match abi.functions.get(func).map(Vec::as_slice) {
+        Some([function]) => Ok(function.clone()),
+        Some(overloads) if !overloads.is_empty() => Err(EvmError::Eyre(eyre::eyre!(
+            "function {func} is overloaded, call it by its full signature instead"
+        ))),
+        _ => Err(EvmError::Eyre(eyre::eyre!("function {func} not found"))),
+    }
+}
+
+/// The outcome of a successful contract call.
+#[derive(Debug, Clone)]
+pub struct CallResult<R> {
+    /// The decoded return value
+    pub result: R,
+    /// The ABI-encoded return data the result was decoded from
+    pub raw_output: Bytes,
+    /// The address of the called contract
+    pub address: Address,
+    /// The raw logs emitted during the call
+    pub logs: Vec<Log>,
+    /// The gas used by the call
+    pub gas_used: u64,
+    /// The accounts and storage changed by the call
+    state_diff: StateDiff,
+    /// The storage slots accessed by the call, if recorded
+    accesses: AccountAccesses,
+    /// The call trace, with labelled addresses
+    trace: Option<CallTraceArena>,
+}
+
+impl<R> CallResult<R> {
+    /// The balance, nonce and storage changes the call made to every account it touched, empty
+    /// unless diffing was started with [`Contract::record_state_diffs`]. Static calls report the
+    /// changes they would have made.
+    pub fn state_diff(&self) -> &StateDiff {
+        &self.state_diff
+    }
+
+    /// The storage slots read and written by the call in every account it touched. Empty unless
+    /// recording was started with [`Contract::record_accesses`].
+    pub fn accesses(&self) -> &AccountAccesses {
+        &self.accesses
+    }
+
+    /// The call trace serialized to JSON, with the address, calldata, output, gas and success
+    /// of every call frame. `null` if the call wasn't traced.
+    pub fn trace_json(&self) -> serde_json::Value {
+        serde_json::to_value(&self.trace).unwrap_or_default()
+    }
+
+    /// The decoded `console.log` output of the call and all its subcalls, in emission order.
+    pub fn console_logs(&self) -> Vec<String> {
+        decode_console_logs(&self.logs)
+    }
+
+    /// Decodes all the `E` events emitted by the called contract, logs emitted by other
+    /// contracts are skipped.
+    pub fn events<E: EthEvent>(&self) -> Vec<E> {
+        self.events_from(self.address)
+    }
+
+    /// Decodes all the `E` events emitted by the contract at `emitter` during the call,
+    /// including the contracts called by the called contract.
+    pub fn events_from<E: EthEvent>(&self, emitter: Address) -> Vec<E> {
+        self.emitted_events()
+            .into_iter()
+            .filter(|(address, _)| *address == emitter)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    /// Decodes all the `E` events emitted during the call by any contract, paired with the
+    /// address of the contract that emitted them, in emission order. Indexed dynamic parameters
+    /// are only stored as their hash, so `E` must declare them as `H256` like abigen does,
+    /// events that fail to decode are skipped.
+    pub fn emitted_events<E: EthEvent>(&self) -> Vec<(Address, E)> {
+        decode_events(&self.logs)
+    }
+
+    /// Replaces the result of the call, keeping its raw output, logs, gas and state changes.
+    fn with_result<T>(self, result: T) -> CallResult<T> {
+        let Self { raw_output, address, logs, gas_used, state_diff, accesses, trace, .. } = self;
+        CallResult { result, raw_output, address, logs, gas_used, state_diff, accesses, trace }
+    }
+}
+
+/// The receipt of a transaction sent with [`Contract::send`].
+#[derive(Debug, Clone)]
+pub struct TxReceipt {
+    /// Whether the transaction succeeded
+    pub status: bool,
+    /// The gas used by the transaction
+    pub gas_used: u64,
+    /// The raw logs emitted by the transaction, empty if it failed
+    pub logs: Vec<Log>,
+    /// The return data, or the revert data if the transaction failed
+    pub return_data: Bytes,
+    /// The storage slots accessed by the transaction, if recorded with
+    /// [`Contract::record_accesses`]
+    pub accesses: AccountAccesses,
+}
+
+/// An event decoded against a contract ABI, see [`Contract::decoded_events`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    /// The address of the contract that emitted the event
+    pub address: Address,
+    /// The name of the event
+    pub name: String,
+    /// The decoded parameters, in declaration order
+    pub params: Vec<LogParam>,
+}
+
+/// Decodes the `E` events in the logs, paired with the address of the contract that emitted them.
+fn decode_events<E: EthEvent>(logs: &[Log]) -> Vec<(Address, E)> {
+    logs.iter()
+        .filter(|log| E::is_anonymous() || log.topics.first() == Some(&E::signature()))
+        .filter_map(|log| {
+            let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+            Some((log.address, E::decode_log(&raw).ok()?))
+        })
+        .collect()
+}
+
+/// Decodes the logs against the non-anonymous events in the ABI, skipping those that don't match.
+fn decode_abi_events(abi: &Abi, logs: &[Log]) -> Vec<DecodedEvent> {
+    logs.iter()
+        .filter_map(|log| {
+            let event = abi
+                .events()
+                .find(|event| !event.anonymous && log.topics.first() == Some(&event.signature()))?;
+            let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+            let params = event.parse_log(raw).ok()?.params;
+            Some(DecodedEvent { address: log.address, name: event.name.clone(), params })
+        })
+        .collect()
+}
+
+/// Checks that the logs contain a match for every expected event, in order.
+fn check_emits(expected_emits: Vec<ExpectedEmit>, logs: &[Log]) -> Result<(), CallError> {
+    let mut logs = logs.iter();
+    for expected in expected_emits {
+        if !logs.any(|log| (expected.matches)(log)) {
+            return Err(CallError::MissingEvent(expected.name))
+        }
+    }
+    Ok(())
+}
+
+/// An event the next call must emit, see [`Contract::expect_emit`].
+struct ExpectedEmit {
+    /// The name of the event
+    name: String,
+    /// Whether a log matches the expected event on the checked fields
+    matches: Box<dyn Fn(&Log) -> bool + Send + Sync>,
+}
+
+/// The fields of an event, a struct deriving `EthEvent` tokenizes into a tuple of them.
+fn fields(token: Token) -> Vec<Token> {
+    match token {
+        Token::Tuple(fields) => fields,
+        token => vec![token],
+    }
+}
+
+/// Options for a single contract call.
+struct CallOpts {
+    /// The `msg.sender` of the call
+    caller: Address,
+    /// The `tx.origin` of the call if it differs from the caller, defaults to the configured
+    /// origin, see [`RunnerBuilder::tx_origin`](crate::RunnerBuilder::tx_origin)
+    origin: Option<Address>,
+    /// The wei sent along with the call
+    value: U256,
+    /// Whether the state changes made by the call are committed to the backend
+    commit: bool,
+    /// Whether the call is logged and recorded in the gas report, coverage and last trace
+    record: bool,
+    /// Overrides the executor gas limit for this call
+    gas_limit: Option<u64>,
+    /// The addresses and storage slots that are warm from the start of the call
+    access_list: AccessList,
+}
+
+impl CallOpts {
+    /// Committing and recorded call from `caller` without value, using the executor gas limit.
+    fn new(caller: Address) -> Self {
+        Self {
+            caller,
+            origin: None,
+            value: U256::zero(),
+            commit: true,
+            record: true,
+            gas_limit: None,
+            access_list: Default::default(),
+        }
+    }
+}
+
+/// Decodes the calldata of the traced call if its selector matches one of `functions`, leaving
+/// it raw otherwise.
+fn decode_call(trace: &mut CallTrace, functions: &BTreeMap<[u8; 4], Function>) {
+    if matches!(trace.kind, CallKind::Create | CallKind::Create2) {
+        return
+    }
+    let RawOrDecodedCall::Raw(data) = &trace.data else { return };
+    let Some(function) = data.get(..4).and_then(|selector| functions.get(selector)) else { return };
+    if let Ok(args) = function.decode_input(&data[4..]) {
+        let args = args.iter().map(format_token).collect();
+        trace.data = RawOrDecodedCall::Decoded(function.name.clone(), function.signature(), args);
+    }
+}
+
+/// The gas a transaction with the given calldata costs before executing any code.
+fn intrinsic_gas(calldata: &[u8]) -> u64 {
+    let zeros = calldata.iter().filter(|byte| **byte == 0).count() as u64;
+    21_000 + zeros * 4 + (calldata.len() as u64 - zeros) * 16
+}
+
+/// Wraps an arbitrary error into an [`EvmError`].
+fn evm_err(err: impl Into<eyre::Report>) -> EvmError {
+    EvmError::Eyre(err.into())
+}
+
+/// Wraps an ABI encoding or decoding error into a [`CallError::Decode`].
+fn decode_err(err: impl ToString) -> CallError {
+    CallError::Decode(err.to_string())
+}
+
+/// How much is logged for every call, each level includes the output of the ones below.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Nothing is logged
+    Silent,
+    /// The gas used by the call
+    Gas,
+    /// The decoded `console.log` output of the call
+    #[default]
+    Logs,
+    /// The call trace, with labelled addresses
+    Traces,
+}
+
+/// Emits the output of the call selected by `verbosity` as `tracing` events.
+fn log_call(
+    verbosity: Verbosity,
+    func: &str,
+    call: &RawCallResult,
+    trace: Option<&CallTraceArena>,
+) {
+    if verbosity >= Verbosity::Gas {
+        tracing::info!(target: "forge_testsuite", func, gas_used = call.gas_used, "gas used");
+    }
+    if verbosity >= Verbosity::Logs {
+        for log in decode_console_logs(&call.logs) {
+            tracing::info!(target: "forge_testsuite::console", func, "{log}");
+        }
+    }
+    if let Some(trace) = trace.filter(|_| verbosity >= Verbosity::Traces) {
+        tracing::info!(target: "forge_testsuite::trace", func, "\n{trace}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_intrinsic_gas_per_calldata_byte() {
+        assert_eq!(intrinsic_gas(&[]), 21_000);
+        assert_eq!(intrinsic_gas(&[0, 0, 1]), 21_000 + 2 * 4 + 16);
+    }
+
+    #[test]
+    fn decodes_indexed_dynamic_params_as_hashes() {
+        let abi =
+            abi::parse_abi(&["event Named(string indexed name)", "event Other(uint256 value)"])
+                .unwrap();
+        let event = abi.event("Named").unwrap();
+        let name = crate::keccak256("alice");
+        let emitter = Address::repeat_byte(0x42);
+        let logs = vec![
+            Log { address: emitter, topics: vec![event.signature(), name], ..Default::default() },
+            Log { topics: vec![H256::repeat_byte(0xff)], ..Default::default() },
+        ];
+
+        let events = decode_abi_events(&abi, &logs);
+        assert_eq!(
+            events,
+            vec![DecodedEvent {
+                address: emitter,
+                name: "Named".into(),
+                params: vec![LogParam {
+                    name: "name".into(),
+                    value: Token::FixedBytes(name.as_bytes().to_vec()),
+                }],
+            }]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line coverage of the solidity sources exercised by contract calls.
+
+use ethers::{
+    types::{Bytes, H256},
+    utils::hex,
+};
+use ethers_solc::{
+    artifacts::BytecodeObject, sourcemap::SourceMap, Artifact, ProjectCompileOutput,
+};
+use foundry_evm::coverage::HitMaps;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Source information needed to map the program counters hit during execution back to lines in
+/// the solidity sources.
+#[derive(Debug, Default)]
+pub(crate) struct CoverageSources {
+    /// Path and contents of every source file, by solc source id
+    files: BTreeMap<u32, (PathBuf, String)>,
+    /// The creation and runtime bytecode of every compiled contract
+    bytecodes: Vec<SourceMappedBytecode>,
+}
+
+/// Bytecode along with its source map.
+#[derive(Debug)]
+struct SourceMappedBytecode {
+    /// The compiled bytecode, unlinked library placeholders are zeroed out
+    code: Vec<u8>,
+    /// Ranges of the bytecode that differ once deployed, i.e. linked libraries and immutables
+    masked: Vec<Range<usize>>,
+    /// The source map for the bytecode
+    source_map: SourceMap,
+    /// Whether this is creation code, which runs with the constructor arguments appended
+    creation: bool,
+}
+
+impl SourceMappedBytecode {
+    /// Whether the executed bytecode was compiled from this contract.
+    fn matches(&self, code: &[u8]) -> bool {
+        // constructor arguments are appended to the creation code, only compare the code itself.
+        let code = if self.creation {
+            code.get(..self.code.len())
+        } else {
+            (code.len() == self.code.len()).then_some(code)
+        };
+        code.map_or(false, |code| {
+            code.iter()
+                .zip(&self.code)
+                .enumerate()
+                .all(|(i, (a, b))| a == b || self.masked.iter().any(|range| range.contains(&i)))
+        })
+    }
+}
+
+impl CoverageSources {
+    /// Collects the sources and source maps from the compiler output.
+    pub(crate) fn new(output: &ProjectCompileOutput) -> Self {
+        let files = output
+            .clone()
+            .into_output()
+            .sources
+            .into_sources_with_version()
+            .filter_map(|(path, source, _)| {
+                let contents = fs::read_to_string(&path).ok()?;
+                Some((source.id, (path.into(), contents)))
+            })
+            .collect();
+
+        let mut bytecodes = vec![];
+        for (_, artifact) in output.artifacts() {
+            if let (Some(Ok(source_map)), Some(bytecode)) =
+                (artifact.get_source_map(), artifact.bytecode.as_ref())
+            {
+                let masked =
+                    offsets(bytecode.link_references.values().flat_map(|libs| libs.values()));
+                if let Some(code) = decode_object(&bytecode.object) {
+                    let bytecode =
+                        SourceMappedBytecode { code, masked, source_map, creation: true };
+                    bytecodes.push(bytecode);
+                }
+            }
+
+            let deployed = artifact.deployed_bytecode.as_ref();
+            if let (Some(Ok(source_map)), Some(bytecode), Some(deployed)) = (
+                artifact.get_source_map_deployed(),
+                deployed.and_then(|deployed| deployed.bytecode.as_ref()),
+                deployed,
+            ) {
+                let mut masked =
+                    offsets(bytecode.link_references.values().flat_map(|libs| libs.values()));
+                masked.extend(offsets(deployed.immutable_references.values()));
+                if let Some(code) = decode_object(&bytecode.object) {
+                    let bytecode =
+                        SourceMappedBytecode { code, masked, source_map, creation: false };
+                    bytecodes.push(bytecode);
+                }
+            }
+        }
+
+        Self { files, bytecodes }
+    }
+}
+
+/// The line hits collected across all calls made to a contract.
+#[derive(Debug, Clone, Default)]
+pub struct CoverageReport {
+    /// The sources used to map hits to lines
+    sources: Arc<CoverageSources>,
+    /// The executed bytecode and the number of times each program counter was hit, by code hash
+    hits: BTreeMap<H256, (Bytes, BTreeMap<usize, u64>)>,
+}
+
+impl CoverageReport {
+    /// Creates an empty report that maps hits using the given sources.
+    pub(crate) fn new(sources: Arc<CoverageSources>) -> Self {
+        Self { sources, hits: Default::default() }
+    }
+
+    /// Adds the hits recorded by the executor to the report.
+    pub(crate) fn merge(&mut self, hit_maps: &HitMaps) {
+        for (hash, hit_map) in hit_maps.0.iter() {
+            let (_, hits) = self
+                .hits
+                .entry(*hash)
+                .or_insert_with(|| (hit_map.bytecode.clone(), Default::default()));
+            for (pc, count) in &hit_map.hits {
+                *hits.entry(*pc).or_default() += count;
+            }
+        }
+    }
+
+    /// The number of hits for every instrumented source line, grouped by source file. Lines
+    /// that were never executed have zero hits.
+    pub fn lines(&self) -> BTreeMap<PathBuf, BTreeMap<usize, u64>> {
+        let mut lines = BTreeMap::<PathBuf, BTreeMap<usize, u64>>::new();
+
+        for (code, hits) in self.hits.values() {
+            let Some(bytecode) = self.sources.bytecodes.iter().find(|b| b.matches(code)) else {
+                continue
+            };
+
+            for (ic, pc) in instruction_pcs(code).into_iter().enumerate() {
+                let Some(element) = bytecode.source_map.get(ic) else { break };
+                let Some((path, contents)) =
+                    element.index.and_then(|index| self.sources.files.get(&index))
+                else {
+                    continue
+                };
+
+                let line = line_number(contents, element.offset);
+                let count = hits.get(&pc).copied().unwrap_or_default();
+                let line_hits = lines.entry(path.clone()).or_default().entry(line).or_default();
+                *line_hits = (*line_hits).max(count);
+            }
+        }
+
+        lines
+    }
+
+    /// Renders the report in the lcov tracefile format.
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (path, lines) in self.lines() {
+            let _ = writeln!(lcov, "TN:\nSF:{}", path.display());
+            for (line, hits) in &lines {
+                let _ = writeln!(lcov, "DA:{line},{hits}");
+            }
+            let hit = lines.values().filter(|hits| **hits > 0).count();
+            let _ = writeln!(lcov, "LF:{}\nLH:{hit}\nend_of_record", lines.len());
+        }
+        lcov
+    }
+
+    /// Writes the report in the lcov tracefile format to `path`.
+    pub fn to_lcov_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_lcov())
+    }
+}
+
+/// Decodes the bytecode object, zeroing out any unlinked library placeholders.
+fn decode_object(object: &BytecodeObject) -> Option<Vec<u8>> {
+    match object {
+        BytecodeObject::Bytecode(code) => Some(code.to_vec()),
+        BytecodeObject::Unlinked(code) => {
+            let code = code.strip_prefix("0x").unwrap_or(code);
+            let mut zeroed = String::with_capacity(code.len());
+            let mut rest = code;
+            while let Some(start) = rest.find("__") {
+                zeroed.push_str(&rest[..start]);
+                // library placeholders are 40 characters long, the size of an address.
+                zeroed.push_str(&"0".repeat(40));
+                rest = rest.get(start + 40..)?;
+            }
+            zeroed.push_str(rest);
+            hex::decode(zeroed).ok()
+        },
+    }
+}
+
+/// Converts link and immutable reference offsets into byte ranges.
+fn offsets<'a>(
+    refs: impl Iterator<Item = &'a Vec<ethers_solc::artifacts::Offsets>>,
+) -> Vec<Range<usize>> {
+    refs.flatten()
+        .map(|offset| offset.start as usize..(offset.start + offset.length) as usize)
+        .collect()
+}
+
+/// The program counter of every instruction in the bytecode, indexed by instruction.
+fn instruction_pcs(code: &[u8]) -> Vec<usize> {
+    let mut pcs = vec![];
+    let mut pc = 0;
+    while pc < code.len() {
+        pcs.push(pc);
+        let op = code[pc];
+        // PUSH1..PUSH32 are followed by their immediate data.
+        pc += match op {
+            0x60..=0x7f => (op - 0x5f) as usize + 1,
+            _ => 1,
+        };
+    }
+    pcs
+}
+
+/// The 1-based line number of the byte at `offset` in the source.
+fn line_number(contents: &str, offset: usize) -> usize {
+    contents.as_bytes().iter().take(offset).filter(|byte| **byte == b'\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_linked_and_unlinked_bytecode() {
+        let linked = BytecodeObject::Bytecode(vec![0x60, 0x80, 0x60, 0x40].into());
+        assert_eq!(decode_object(&linked), Some(vec![0x60, 0x80, 0x60, 0x40]));
+
+        let placeholder = format!("__${}$__", "ab".repeat(17));
+        assert_eq!(placeholder.len(), 40);
+        let unlinked = BytecodeObject::Unlinked(format!("0x73{placeholder}3014"));
+        let mut expected = vec![0x73];
+        expected.extend([0; 20]);
+        expected.extend([0x30, 0x14]);
+        assert_eq!(decode_object(&unlinked), Some(expected));
+
+        // truncated placeholder
+        let truncated = BytecodeObject::Unlinked(format!("0x73{}", &placeholder[..20]));
+        assert_eq!(decode_object(&truncated), None);
+    }
+
+    #[test]
+    fn matches_creation_code_with_constructor_args() {
+        let bytecode = |creation| SourceMappedBytecode {
+            code: vec![0x60, 0x80, 0x73, 0x00, 0x00, 0x00],
+            masked: vec![3..6],
+            source_map: vec![],
+            creation,
+        };
+        let (creation, runtime) = (bytecode(true), bytecode(false));
+
+        let deployed = [0x60, 0x80, 0x73, 0xaa, 0xbb, 0xcc];
+        assert!(creation.matches(&deployed));
+        assert!(runtime.matches(&deployed));
+
+        let with_args = [&deployed[..], &[0x00; 32]].concat();
+        assert!(creation.matches(&with_args));
+        assert!(!runtime.matches(&with_args));
+
+        assert!(!creation.matches(&deployed[..5]));
+        assert!(!creation.matches(&[0x60, 0x40, 0x73, 0xaa, 0xbb, 0xcc]));
+    }
+
+    #[test]
+    fn skips_push_data() {
+        // PUSH1 0x80 PUSH1 0x40 MSTORE PUSH32 <32 bytes> STOP
+        let mut code = vec![0x60, 0x80, 0x60, 0x40, 0x52, 0x7f];
+        code.extend([0xff; 32]);
+        code.push(0x00);
+        assert_eq!(instruction_pcs(&code), vec![0, 2, 4, 5, 38]);
+
+        // push data running past the end of the code
+        assert_eq!(instruction_pcs(&[0x00, 0x61, 0x01]), vec![0, 1]);
+        assert!(instruction_pcs(&[]).is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Error types returned by the runner and contract handles.
+
+use ethers::{
+    abi::{Abi, AbiDecode},
+    types::{Bytes, U256},
+};
+use ethers_solc::{error::SolcError, ProjectCompileOutput};
+use foundry_evm::{
+    executor::{EvmError, SpecId},
+    revm::interpreter::InstructionResult,
+    Address,
+};
+use std::{fmt, path::PathBuf};
+
+/// Errors that can occur while setting up a [`Runner`](crate::Runner).
+#[derive(Debug, thiserror::Error)]
+pub enum RunnerError {
+    /// The provided project root does not exist.
+    #[error("project root {0:?} does not exist")]
+    MissingRoot(PathBuf),
+    /// The `foundry.toml` file at the project root could not be read or is invalid.
+    #[error("failed to load foundry.toml: {0}")]
+    Config(eyre::Report),
+    /// The `remappings.txt` file could not be read or a remapping is malformed.
+    #[error("failed to parse remappings: {0}")]
+    Remappings(String),
+    /// The project failed to compile, carries the compiler output with its diagnostics.
+    #[error("Compiler errors: {0}")]
+    Compilation(Box<ProjectCompileOutput>),
+    /// The requested solc version is not installed and could not be downloaded.
+    #[error("failed to obtain solc {0}: {1}")]
+    SolcVersion(semver::Version, SolcError),
+    /// Error originating from solc or the project configuration.
+    #[error(transparent)]
+    Solc(#[from] SolcError),
+    /// The EVM spec enables opcodes the EVM contracts are executed on doesn't implement.
+    #[error("unsupported EVM spec {0:?}, specs up to Shanghai are supported")]
+    UnsupportedSpec(SpecId),
+    /// The multi contract runner could not be built.
+    #[error("failed to build contract runner: {0}")]
+    Build(eyre::Report),
+    /// No compiled contract with the given name exists in the project.
+    #[error("contract {0} not found")]
+    ContractNotFound(String),
+    /// The runtime code of the contract exceeds the code size limit.
+    #[error("contract {0} too large: {1} bytes exceeds the limit of {2} bytes")]
+    ContractTooLarge(String, usize, usize),
+    /// The constructor arguments don't match the constructor in the contract ABI.
+    #[error("invalid constructor arguments: {0}")]
+    ConstructorArgs(String),
+    /// The contract depends on libraries that weren't provided for linking.
+    #[error("unresolved library placeholders: {0:?}")]
+    UnlinkedLibraries(Vec<String>),
+    /// There is no contract deployed at the address being attached to.
+    #[error("no code at address {0:?}")]
+    NoCode(Address),
+    /// A fork could not be created or selected.
+    #[error("fork failed: {0}")]
+    Fork(eyre::Report),
+    /// Deploying the contract or running its `setUp` failed.
+    #[error("contract setup failed: {0}")]
+    Setup(String),
+}
+
+/// Errors returned when calling a contract.
+#[derive(Debug, thiserror::Error)]
+pub enum CallError {
+    /// The call reverted, with the decoded reason and the raw revert data.
+    #[error("call reverted: {0}")]
+    Revert(RevertReason, Bytes),
+    /// The call ran out of gas.
+    #[error("call ran out of gas")]
+    OutOfGas,
+    /// The arguments couldn't be encoded or the return data doesn't match the function outputs.
+    #[error("failed to decode call: {0}")]
+    Decode(String),
+    /// The call halted without reverting, e.g. on an invalid opcode or a stack overflow.
+    #[error("call halted: {0:?}")]
+    Halt(InstructionResult),
+    /// The call succeeded without emitting an event expected with
+    /// [`Contract::expect_emit`](crate::Contract::expect_emit).
+    #[error("expected event {0} was not emitted")]
+    MissingEvent(String),
+    /// The call could not be executed.
+    #[error(transparent)]
+    Evm(#[from] EvmError),
+}
+
+impl CallError {
+    /// The decoded revert reason, if the call reverted.
+    pub fn reason(&self) -> Option<&RevertReason> {
+        match self {
+            CallError::Revert(reason, _) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// The raw revert data, if the call reverted. It's kept even when the reason couldn't be
+    /// decoded, e.g. for custom errors defined outside the project.
+    pub fn data(&self) -> Option<&Bytes> {
+        match self {
+            CallError::Revert(_, data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl From<CallError> for EvmError {
+    fn from(err: CallError) -> Self {
+        match err {
+            CallError::Evm(err) => err,
+            err => EvmError::Eyre(eyre::eyre!("{err}")),
+        }
+    }
+}
+
+/// The decoded reason a call reverted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    /// A `revert(string)` or `require(bool, string)` message.
+    Error(String),
+    /// A `Panic(uint256)` code, e.g. `0x11` for arithmetic overflow.
+    Panic(U256),
+    /// The name of a custom error defined in the contract ABI or the project's errors.
+    Custom(String),
+    /// Revert data that doesn't match any known error.
+    Unknown(Bytes),
+}
+
+impl RevertReason {
+    /// Selector of `Error(string)`
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    /// Selector of `Panic(uint256)`
+    const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+    /// Decodes the revert data, matching custom errors against the contract ABI and the errors
+    /// registry.
+    pub fn decode(data: &[u8], abi: &Abi, errors: Option<&Abi>) -> Self {
+        let unknown = || RevertReason::Unknown(data.to_vec().into());
+        if data.len() < 4 {
+            return unknown()
+        }
+
+        let (selector, payload) = data.split_at(4);
+        if selector == Self::ERROR_SELECTOR {
+            return String::decode(payload).map(RevertReason::Error).unwrap_or_else(|_| unknown())
+        }
+        if selector == Self::PANIC_SELECTOR {
+            return U256::decode(payload).map(RevertReason::Panic).unwrap_or_else(|_| unknown())
+        }
+
+        abi.errors()
+            .chain(errors.into_iter().flat_map(|errors| errors.errors()))
+            .find(|error| error.signature()[..4] == *selector)
+            .map(|error| RevertReason::Custom(error.name.clone()))
+            .unwrap_or_else(unknown)
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Error(message) => write!(f, "{message}"),
+            RevertReason::Panic(code) => write!(f, "panic code {code:#x}"),
+            RevertReason::Custom(name) => write!(f, "{name}"),
+            RevertReason::Unknown(data) => write!(f, "{data}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{self, AbiError, Param, ParamType, Token};
+
+    fn encode(selector: [u8; 4], tokens: &[Token]) -> Vec<u8> {
+        [&selector[..], &abi::encode(tokens)].concat()
+    }
+
+    fn abi_with_error(name: &str, inputs: Vec<ParamType>) -> Abi {
+        let inputs = inputs
+            .into_iter()
+            .map(|kind| Param { name: String::new(), kind, internal_type: None })
+            .collect();
+        let mut abi = Abi::default();
+        abi.errors
+            .insert(name.to_string(), vec![AbiError { name: name.to_string(), inputs }]);
+        abi
+    }
+
+    #[test]
+    fn decodes_revert_reasons() {
+        let abi = abi_with_error("Unauthorized", vec![ParamType::Address]);
+        let errors = abi_with_error("Expired", vec![]);
+
+        let data = encode(RevertReason::ERROR_SELECTOR, &[Token::String("not owner".into())]);
+        assert_eq!(
+            RevertReason::decode(&data, &abi, None),
+            RevertReason::Error("not owner".into())
+        );
+
+        let data = encode(RevertReason::PANIC_SELECTOR, &[Token::Uint(0x11.into())]);
+        assert_eq!(RevertReason::decode(&data, &abi, None), RevertReason::Panic(0x11.into()));
+
+        let unauthorized = abi.errors().next().unwrap().signature();
+        let data =
+            encode(unauthorized[..4].try_into().unwrap(), &[Token::Address(Default::default())]);
+        assert_eq!(
+            RevertReason::decode(&data, &abi, None),
+            RevertReason::Custom("Unauthorized".into())
+        );
+
+        let expired = errors.errors().next().unwrap().signature();
+        let expired = encode(expired[..4].try_into().unwrap(), &[]);
+        assert_eq!(
+            RevertReason::decode(&expired, &abi, Some(&errors)),
+            RevertReason::Custom("Expired".into())
+        );
+        assert_eq!(
+            RevertReason::decode(&expired, &abi, None),
+            RevertReason::Unknown(expired.clone().into())
+        );
+    }
+
+    #[test]
+    fn keeps_malformed_revert_data() {
+        let abi = Abi::default();
+        for data in [vec![], vec![0x08, 0xc3], RevertReason::ERROR_SELECTOR.to_vec()] {
+            assert_eq!(
+                RevertReason::decode(&data, &abi, None),
+                RevertReason::Unknown(data.clone().into())
+            );
+        }
+    }
+}
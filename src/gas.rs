@@ -0,0 +1,223 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregation of gas usage across contract calls.
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+/// Gas usage statistics of a single function across all of its calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GasStats {
+    /// The lowest gas used by a call
+    pub min: u64,
+    /// The highest gas used by a call
+    pub max: u64,
+    /// The mean gas used across all calls
+    pub mean: u64,
+    /// The number of calls
+    pub count: u64,
+}
+
+/// Collects the gas used by every contract call, grouped by contract and function, like
+/// `forge test --gas-report`.
+#[derive(Debug, Clone, Default)]
+pub struct GasReport {
+    calls: BTreeMap<String, BTreeMap<String, Vec<u64>>>,
+}
+
+impl GasReport {
+    /// Records the gas used by a call to `func` on `contract`.
+    pub(crate) fn record(&mut self, contract: &str, func: &str, gas_used: u64) {
+        self.calls
+            .entry(contract.to_string())
+            .or_default()
+            .entry(func.to_string())
+            .or_default()
+            .push(gas_used);
+    }
+
+    /// Gas statistics for calls to `func` on `contract`, if it was called at all.
+    pub fn stats(&self, contract: &str, func: &str) -> Option<GasStats> {
+        self.calls.get(contract)?.get(func).map(|calls| stats(calls))
+    }
+
+    /// Iterates over the gas statistics of every called function as `(contract, function,
+    /// stats)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, GasStats)> {
+        self.calls.iter().flat_map(|(contract, functions)| {
+            functions
+                .iter()
+                .map(move |(func, calls)| (contract.as_str(), func.as_str(), stats(calls)))
+        })
+    }
+
+    /// Compares the mean gas of every called function against the baseline in the
+    /// `.gas-snapshot` file at `path`, like `forge snapshot --check`. The baseline is written if
+    /// the file doesn't exist yet. Fails with a diff if the gas of any function drifted by more
+    /// than `tolerance` percent, or if functions were added or removed since the baseline.
+    pub fn assert_against(&self, path: impl AsRef<Path>, tolerance: f64) -> Result<(), String> {
+        let path = path.as_ref();
+        let current = self
+            .iter()
+            .map(|(contract, func, stats)| (format!("{contract}:{func}"), stats.mean))
+            .collect::<BTreeMap<_, _>>();
+
+        if !path.exists() {
+            let snapshot = current
+                .iter()
+                .map(|(name, gas)| format!("{name} (gas: {gas})\n"))
+                .collect::<String>();
+            return fs::write(path, snapshot)
+                .map_err(|err| format!("failed to write {}: {err}", path.display()))
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        let mut baseline = BTreeMap::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let entry = line
+                .trim()
+                .strip_suffix(')')
+                .and_then(|line| line.rsplit_once(" (gas: "))
+                .and_then(|(name, gas)| Some((name.to_string(), gas.parse::<u64>().ok()?)))
+                .ok_or_else(|| format!("malformed gas snapshot entry: {line}"))?;
+            baseline.insert(entry.0, entry.1);
+        }
+
+        let mut diff = vec![];
+        for (name, gas) in &current {
+            match baseline.get(name) {
+                Some(expected) => {
+                    let delta = *gas as f64 - *expected as f64;
+                    if delta.abs() > *expected as f64 * tolerance / 100.0 {
+                        diff.push(format!("{name}: {expected} -> {gas} ({delta:+})"));
+                    }
+                },
+                None => diff.push(format!("{name}: added (gas: {gas})")),
+            }
+        }
+        for (name, expected) in &baseline {
+            if !current.contains_key(name) {
+                diff.push(format!("{name}: removed (gas: {expected})"));
+            }
+        }
+
+        if diff.is_empty() {
+            return Ok(())
+        }
+        Err(format!("gas snapshot {} doesn't match:\n{}", path.display(), diff.join("\n")))
+    }
+}
+
+impl fmt::Display for GasReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "| {:<24} | {:<32} | {:>10} | {:>10} | {:>10} | {:>8} |",
+            "Contract", "Function", "min", "mean", "max", "# calls"
+        )?;
+        for (contract, func, GasStats { min, max, mean, count }) in self.iter() {
+            writeln!(
+                f,
+                "| {contract:<24} | {func:<32} | {min:>10} | {mean:>10} | {max:>10} | {count:>8} |"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn stats(calls: &[u64]) -> GasStats {
+    let count = calls.len() as u64;
+    let total = calls.iter().map(|gas| *gas as u128).sum::<u128>();
+    GasStats {
+        min: calls.iter().copied().min().unwrap_or_default(),
+        max: calls.iter().copied().max().unwrap_or_default(),
+        mean: total.checked_div(count as u128).unwrap_or_default() as u64,
+        count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("{name}-{}.gas-snapshot", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn report() -> GasReport {
+        let mut report = GasReport::default();
+        report.record("Token", "transfer", 50_000);
+        report.record("Token", "transfer", 52_000);
+        report.record("Token", "approve", 24_000);
+        report
+    }
+
+    #[test]
+    fn aggregates_calls() {
+        let stats = report().stats("Token", "transfer").unwrap();
+        assert_eq!(stats, GasStats { min: 50_000, max: 52_000, mean: 51_000, count: 2 });
+        assert_eq!(report().stats("Token", "mint"), None);
+    }
+
+    #[test]
+    fn writes_missing_baseline() {
+        let path = snapshot_path("writes-missing-baseline");
+        report().assert_against(&path, 0.0).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Token:approve (gas: 24000)\nToken:transfer (gas: 51000)\n"
+        );
+        report().assert_against(&path, 0.0).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compares_within_tolerance() {
+        let path = snapshot_path("compares-within-tolerance");
+        fs::write(&path, "Token:approve (gas: 24000)\n\nToken:transfer (gas: 50000)\n").unwrap();
+
+        // transfer drifted by 2%.
+        report().assert_against(&path, 2.0).unwrap();
+        let err = report().assert_against(&path, 1.0).unwrap_err();
+        assert!(err.contains("Token:transfer: 50000 -> 51000 (+1000)"), "{err}");
+        assert!(!err.contains("Token:approve"), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_added_and_removed_functions() {
+        let path = snapshot_path("reports-added-and-removed-functions");
+        fs::write(&path, "Token:transfer (gas: 51000)\nToken:mint (gas: 60000)\n").unwrap();
+
+        let err = report().assert_against(&path, 0.0).unwrap_err();
+        assert!(err.contains("Token:approve: added (gas: 24000)"), "{err}");
+        assert!(err.contains("Token:mint: removed (gas: 60000)"), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_baseline() {
+        let path = snapshot_path("rejects-malformed-baseline");
+        fs::write(&path, "Token:transfer 51000\n").unwrap();
+
+        let err = report().assert_against(&path, 0.0).unwrap_err();
+        assert!(err.contains("malformed gas snapshot entry: Token:transfer 51000"), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+}
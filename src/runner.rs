@@ -0,0 +1,642 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The runner responsible for deploying the compiled project contracts.
+
+use crate::{
+    builder::supported_spec, compute_create2_address, coverage::CoverageSources, CallError,
+    CallResult, Contract, GasReport, RunnerBuilder, RunnerError, Verbosity,
+};
+use ethers::{
+    abi::{Abi, Detokenize, Function, Token, Tokenize},
+    types::{Bytes, H256, U256},
+    utils::{get_contract_address, keccak256},
+};
+use ethers_solc::{ArtifactId, ProjectCompileOutput};
+use forge::{
+    executor::inspector::DEFAULT_CREATE2_DEPLOYER, result::TestSetup, MultiContractRunner,
+};
+use foundry_config::Config;
+use foundry_evm::{
+    decode::decode_revert,
+    executor::{
+        backend::{DatabaseExt, LocalForkId},
+        Backend, EvmError, Executor, ExecutorBuilder, SpecId,
+    },
+    revm::{
+        db::DatabaseRef,
+        primitives::{AccountInfo, BlockEnv, KECCAK_EMPTY, MAX_CODE_SIZE},
+        JournaledState,
+    },
+    trace::TraceKind,
+    utils::{h160_to_b160, h256_to_b256, u256_to_ru256, RuntimeOrHandle},
+    Address,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// ABI-encodes the constructor arguments and appends them to the contract's creation code.
+fn encode_constructor_args(
+    abi: &Abi,
+    code: &Bytes,
+    args: Vec<Token>,
+) -> Result<Bytes, RunnerError> {
+    let Some(constructor) = &abi.constructor else {
+        if !args.is_empty() {
+            return Err(RunnerError::ConstructorArgs(format!(
+                "contract has no constructor, got {} arguments",
+                args.len()
+            )))
+        }
+        return Ok(code.clone())
+    };
+
+    if constructor.inputs.len() != args.len() {
+        return Err(RunnerError::ConstructorArgs(format!(
+            "expected {} arguments, got {}",
+            constructor.inputs.len(),
+            args.len()
+        )))
+    }
+
+    let params = constructor.inputs.iter().map(|param| param.kind.clone()).collect::<Vec<_>>();
+    if !Token::types_check(&args, &params) {
+        let types = params.iter().map(ToString::to_string).collect::<Vec<_>>().join(",");
+        return Err(RunnerError::ConstructorArgs(format!(
+            "arguments don't match constructor({types})"
+        )))
+    }
+
+    constructor
+        .encode_input(code.to_vec(), &args)
+        .map(Into::into)
+        .map_err(|err| RunnerError::ConstructorArgs(err.to_string()))
+}
+
+/// The contract runner. Use this to deploy contracts for executing.
+///
+/// All contracts deployed by a runner live on the same executor, which the returned
+/// [`Contract`] handles share ownership of. Handles don't borrow the runner, so contracts can
+/// interact with each other and outlive the runner that deployed them.
+pub struct Runner {
+    pub(crate) runner: MultiContractRunner,
+    pub(crate) config: Config,
+    pub(crate) output: ProjectCompileOutput,
+    pub(crate) gas_report: Option<Arc<Mutex<GasReport>>>,
+    pub(crate) verbosity: Verbosity,
+    pub(crate) block_time: u64,
+    pub(crate) roll_blocks: bool,
+    pub(crate) prevrandao: Option<H256>,
+    pub(crate) accounts: Vec<(Address, U256)>,
+    pub(crate) coverage: Option<Arc<CoverageSources>>,
+    /// The functions of all project contracts by selector, used for decoding call traces
+    pub(crate) functions: Arc<BTreeMap<[u8; 4], Function>>,
+    /// The executor shared by all deployed contracts, created on the first deployment
+    pub(crate) executor: Option<Arc<Mutex<Executor>>>,
+}
+
+impl AsRef<MultiContractRunner> for Runner {
+    fn as_ref(&self) -> &MultiContractRunner {
+        &self.runner
+    }
+}
+
+impl AsMut<MultiContractRunner> for Runner {
+    fn as_mut(&mut self) -> &mut MultiContractRunner {
+        &mut self.runner
+    }
+}
+
+/// Identifies a fork created with [`Runner::create_fork`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ForkId(LocalForkId);
+
+impl Runner {
+    /// Builds a non-tracing runner, panics if the project could not be compiled.
+    pub fn new(root: PathBuf) -> Self {
+        Self::try_new(root).unwrap()
+    }
+
+    /// Builds a non-tracing runner, returning an error if the project could not be compiled.
+    pub fn try_new(root: PathBuf) -> Result<Self, RunnerError> {
+        RunnerBuilder::new(root).build()
+    }
+
+    /// Overrides the EVM spec used for deploying and executing contracts, defaults to
+    /// [`SpecId::SHANGHAI`]. Fails for the specs [`RunnerBuilder::spec`] rejects.
+    pub fn with_spec(mut self, spec: SpecId) -> Result<Self, RunnerError> {
+        self.runner.evm_spec = supported_spec(spec)?;
+        self.executor = None;
+        Ok(self)
+    }
+
+    /// Forks the state of the chain at `rpc_url` at the given block, or the latest block if
+    /// none is provided. All contracts deployed by this runner will execute against the fork.
+    pub fn with_fork(mut self, rpc_url: String, block: Option<u64>) -> Self {
+        self.runner.evm_opts.fork_url = Some(rpc_url);
+        self.runner.evm_opts.fork_block_number = block;
+        // the fork is created lazily on the next deploy.
+        self.runner.fork = None;
+        self.executor = None;
+        self
+    }
+
+    /// Deploy a contract with the provided name and return a handle for executing it's methods.
+    pub async fn deploy(&mut self, contract_name: &'static str) -> Contract {
+        self.deploy_with_args(contract_name, ()).await.unwrap()
+    }
+
+    /// Deploy a contract with the provided name and call a function on it once, for stateless
+    /// contracts that don't need a handle. Deployment failures are returned as
+    /// [`CallError::Evm`].
+    pub async fn deploy_and_call<T, R>(
+        &mut self,
+        contract_name: &str,
+        func: &str,
+        args: T,
+    ) -> Result<CallResult<R>, CallError>
+    where
+        T: Tokenize,
+        R: Detokenize + Debug,
+    {
+        let mut contract = self
+            .deploy_with_args(contract_name, ())
+            .await
+            .map_err(|err| EvmError::Eyre(eyre::eyre!("{err}")))?;
+        let sender = contract.sender;
+        contract.execute(sender, func, args, U256::zero()).await
+    }
+
+    /// Deploy the contracts with the provided names in order, returning their handles in the same
+    /// order. The contracts share the runner's state, so they can interact with each other.
+    /// Stops at the first contract that fails to deploy.
+    pub async fn deploy_many(
+        &mut self,
+        contract_names: &[&str],
+    ) -> Result<Vec<Contract>, RunnerError> {
+        let mut contracts = Vec::with_capacity(contract_names.len());
+        for name in contract_names {
+            contracts.push(self.deploy_with_args(name, ()).await?);
+        }
+        Ok(contracts)
+    }
+
+    /// Deploy a contract with the provided name without requiring an async runtime, blocking until
+    /// the deployment completes.
+    pub fn deploy_blocking(&mut self, contract_name: &'static str) -> Contract {
+        RuntimeOrHandle::new().block_on(self.deploy(contract_name))
+    }
+
+    /// Deploy a contract with the provided name, passing the given arguments to its constructor.
+    pub async fn deploy_with_args<T: Tokenize>(
+        &mut self,
+        contract_name: &str,
+        args: T,
+    ) -> Result<Contract, RunnerError> {
+        self.deploy_with(contract_name, args, U256::zero()).await
+    }
+
+    /// Deploy a contract with the provided name, sending `value` wei to its payable constructor.
+    pub async fn deploy_with_value(
+        &mut self,
+        contract_name: &str,
+        value: U256,
+    ) -> Result<Contract, RunnerError> {
+        self.deploy_with(contract_name, (), value).await
+    }
+
+    /// Deploy a contract with the provided name, passing the given arguments and sending
+    /// `value` wei to its constructor. The sender is funded if it can't afford the value.
+    pub async fn deploy_with<T: Tokenize>(
+        &mut self,
+        contract_name: &str,
+        args: T,
+        value: U256,
+    ) -> Result<Contract, RunnerError> {
+        let executor = self.executor().await?;
+
+        let (id, (abi, deploy_code, libs)) = self.find_contract(contract_name)?;
+        self.check_code_size(id)?;
+        let code = encode_constructor_args(abi, deploy_code, args.into_tokens())?;
+
+        self.deploy_code(executor, &id.name, abi.clone(), code, libs, value)
+    }
+
+    /// Deploy a contract with the provided name through the CREATE2 deployer, so that it ends up
+    /// at the deterministic address given by [`compute_create2_address`].
+    pub async fn deploy_create2(
+        &mut self,
+        contract_name: &str,
+        salt: H256,
+    ) -> Result<Contract, RunnerError> {
+        self.deploy_create2_with_args(contract_name, salt, ()).await
+    }
+
+    /// Deploy a contract with the provided name through the CREATE2 deployer, passing the given
+    /// arguments to its constructor. The arguments are part of the init code the address is
+    /// computed from.
+    pub async fn deploy_create2_with_args<T: Tokenize>(
+        &mut self,
+        contract_name: &str,
+        salt: H256,
+        args: T,
+    ) -> Result<Contract, RunnerError> {
+        let shared = self.executor().await?;
+
+        let (id, (abi, deploy_code, libs)) = self.find_contract(contract_name)?;
+        self.check_code_size(id)?;
+        let code = encode_constructor_args(abi, deploy_code, args.into_tokens())?;
+        let runner = &self.runner;
+        let sender = runner.sender.unwrap_or_default();
+
+        let mut executor = shared.lock().unwrap();
+        deploy_libraries(&mut executor, sender, libs, runner.errors.as_ref())?;
+        executor.deploy_create2_deployer().map_err(setup_err)?;
+
+        let address =
+            compute_create2_address(DEFAULT_CREATE2_DEPLOYER, salt, keccak256(&code).into());
+        let calldata = [salt.as_bytes(), code.as_ref()].concat();
+        let result = executor
+            .call_raw_committing(sender, DEFAULT_CREATE2_DEPLOYER, calldata.into(), U256::zero())
+            .map_err(setup_err)?;
+        if result.reverted {
+            let reason = decode_revert(&result.result, runner.errors.as_ref(), None)
+                .unwrap_or_else(|_| "unknown reason".to_string());
+            return Err(RunnerError::Setup(reason))
+        }
+        let mut setup = TestSetup {
+            address,
+            logs: result.logs,
+            traces: result
+                .traces
+                .map(|traces| (TraceKind::Deployment, traces))
+                .into_iter()
+                .collect(),
+            labeled_addresses: result.labels,
+            coverage: result.coverage,
+            ..Default::default()
+        };
+        self.run_setup(&mut executor, abi, U256::zero(), &mut setup)?;
+        drop(executor);
+
+        let mut contract =
+            Contract::new(self, shared, &id.name, abi.clone(), address).map_err(setup_err)?;
+        contract.record_deployment(result.gas_used, setup);
+        Ok(contract)
+    }
+
+    /// Deploy a contract with the provided name, linking the libraries it depends on to the given
+    /// addresses instead of predeploying them. Libraries can be keyed either by their name or
+    /// their fully qualified name, e.g. `src/Lib.sol:Lib`.
+    pub async fn deploy_with_libraries(
+        &mut self,
+        contract_name: &str,
+        libs: HashMap<String, Address>,
+    ) -> Result<Contract, RunnerError> {
+        let executor = self.executor().await?;
+
+        let (id, (abi, _, _)) = self.find_contract(contract_name)?;
+        self.check_code_size(id)?;
+        let bytecode = self
+            .output
+            .artifact_ids()
+            .find_map(|(artifact, contract)| (artifact == *id).then_some(contract))
+            .and_then(|contract| contract.bytecode.as_ref())
+            .ok_or_else(|| RunnerError::ContractNotFound(contract_name.to_string()))?;
+
+        let mut object = bytecode.object.clone();
+        let mut unresolved = vec![];
+        for (file, names) in &bytecode.link_references {
+            for name in names.keys() {
+                let fully_qualified = format!("{file}:{name}");
+                match libs.get(name).or_else(|| libs.get(&fully_qualified)) {
+                    Some(address) => {
+                        object.link(file, name, *address);
+                    },
+                    None => unresolved.push(fully_qualified),
+                }
+            }
+        }
+
+        let code = match object.as_bytes() {
+            Some(code) if unresolved.is_empty() => code.clone(),
+            _ => return Err(RunnerError::UnlinkedLibraries(unresolved)),
+        };
+
+        self.deploy_code(executor, &id.name, abi.clone(), code, &[], U256::zero())
+    }
+
+    /// Deploy the provided creation bytecode under the given name, bypassing the compiled project
+    /// contracts. Useful for testing Yul or hand-assembled contracts.
+    pub async fn deploy_bytecode(
+        &mut self,
+        name: &str,
+        code: Bytes,
+        abi: &Abi,
+    ) -> Result<Contract, RunnerError> {
+        let executor = self.executor().await?;
+        self.deploy_code(executor, name, abi.clone(), code, &[], U256::zero())
+    }
+
+    /// Binds the ABI of the named contract to a contract that's already deployed at `address`,
+    /// e.g. on a fork, without deploying it or running `setUp`. Fails if there's no code at the
+    /// address.
+    pub async fn attach(
+        &mut self,
+        contract_name: &str,
+        address: Address,
+    ) -> Result<Contract, RunnerError> {
+        let executor = self.executor().await?;
+        let (id, (abi, _, _)) = self.find_contract(contract_name)?;
+
+        if account_info(&executor.lock().unwrap(), address)?.code_hash == KECCAK_EMPTY {
+            return Err(RunnerError::NoCode(address))
+        }
+
+        Contract::new(self, executor, &id.name, abi.clone(), address).map_err(setup_err)
+    }
+
+    /// Sets the nonce of `address`, like `vm.setNonce`. Setting the nonce of the sender makes the
+    /// address of the next deployment predictable with
+    /// [`compute_create_address`](crate::compute_create_address).
+    pub async fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<(), RunnerError> {
+        let executor = self.executor().await?;
+        let mut executor = executor.lock().unwrap();
+        executor.set_nonce(address, nonce).map_err(setup_err)?;
+        Ok(())
+    }
+
+    /// Creates a fork of the chain at `rpc_url` at the given block, or the latest block if none
+    /// is provided, without selecting it. Switch to it with [`Runner::select_fork`].
+    pub async fn create_fork(
+        &mut self,
+        rpc_url: impl Into<String>,
+        block: Option<u64>,
+    ) -> Result<ForkId, RunnerError> {
+        let executor = self.executor().await?;
+
+        let mut evm_opts = self.runner.evm_opts.clone();
+        evm_opts.fork_url = Some(rpc_url.into());
+        evm_opts.fork_block_number = block;
+        let env = evm_opts.evm_env().await;
+        let fork = evm_opts.get_fork(&self.config, env).expect("fork url is set");
+
+        let id = executor.lock().unwrap().backend.create_fork(fork).map_err(RunnerError::Fork)?;
+        Ok(ForkId(id))
+    }
+
+    /// Makes the fork the active one, like `vm.selectFork`. Subsequent deploys and calls, from
+    /// any contract handle, execute against its state and block environment. Contracts
+    /// deployed on another fork don't exist on the selected one.
+    pub async fn select_fork(&mut self, id: ForkId) -> Result<(), RunnerError> {
+        let executor = self.executor().await?;
+        let mut executor = executor.lock().unwrap();
+        let Executor { backend, env, .. } = &mut *executor;
+        // only carries the accounts persisted across forks, it's discarded after the switch.
+        let mut journaled_state = JournaledState::new(0);
+        backend.select_fork(id.0, env, &mut journaled_state).map_err(RunnerError::Fork)
+    }
+
+    /// The gas used by all calls made so far, empty unless enabled with
+    /// [`RunnerBuilder::gas_report`].
+    pub fn gas_report(&self) -> GasReport {
+        self.gas_report
+            .as_ref()
+            .map(|report| report.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Releases the runner's reference to the executor it shares with the contracts it
+    /// deployed. Nothing is torn down explicitly: the executor is dropped along with the last
+    /// reference to it, once those contracts have also been dropped or closed.
+    pub fn close(mut self) {
+        self.executor.take();
+    }
+
+    /// Creates the fork from the configured rpc url, if it hasn't been created yet.
+    async fn init_fork(&mut self) {
+        let runner = &mut self.runner;
+        if runner.fork.is_none() && runner.evm_opts.fork_url.is_some() {
+            let env = runner.evm_opts.evm_env().await;
+            runner.fork = runner.evm_opts.get_fork(&self.config, env.clone());
+            runner.env = env;
+        }
+    }
+
+    /// Looks up the compiled contract with the given name.
+    fn find_contract(
+        &self,
+        contract_name: &str,
+    ) -> Result<(&ArtifactId, &(Abi, Bytes, Vec<Bytes>)), RunnerError> {
+        self.runner
+            .contracts
+            .iter()
+            .find(|(id, (_, _, _))| id.name == contract_name)
+            .ok_or_else(|| RunnerError::ContractNotFound(contract_name.to_string()))
+    }
+
+    /// Fails if the runtime code of the contract exceeds the code size limit, which would make
+    /// its deployment fail on chain.
+    fn check_code_size(&self, id: &ArtifactId) -> Result<(), RunnerError> {
+        let limit = self.runner.evm_opts.env.code_size_limit.unwrap_or(MAX_CODE_SIZE);
+        let size = self
+            .output
+            .artifact_ids()
+            .find_map(|(artifact, contract)| (artifact == *id).then_some(contract))
+            .and_then(|contract| contract.deployed_bytecode.as_ref())
+            .and_then(|deployed| deployed.bytecode.as_ref())
+            .map(|bytecode| bytecode.object.bytes_len())
+            .unwrap_or_default();
+
+        if size > limit {
+            return Err(RunnerError::ContractTooLarge(id.name.clone(), size, limit))
+        }
+        Ok(())
+    }
+
+    /// The executor shared by all deployed contracts, spawning it on the first deployment.
+    async fn executor(&mut self) -> Result<Arc<Mutex<Executor>>, RunnerError> {
+        if let Some(executor) = &self.executor {
+            return Ok(executor.clone())
+        }
+
+        self.init_fork().await;
+        let runner = &self.runner;
+        let db = Backend::spawn(runner.fork.clone()).await;
+        let mut executor = ExecutorBuilder::default()
+            .with_cheatcodes(runner.cheats_config.clone())
+            .with_config(runner.env.clone())
+            .with_spec(runner.evm_spec)
+            .with_gas_limit(runner.evm_opts.gas_limit())
+            .set_tracing(true)
+            .set_coverage(runner.coverage)
+            .build(db);
+        executor
+            .set_balance(runner.sender.unwrap_or_default(), runner.evm_opts.initial_balance)
+            .map_err(setup_err)?;
+        for (account, balance) in &self.accounts {
+            executor.set_balance(*account, *balance).map_err(setup_err)?;
+        }
+        if let Some(prevrandao) = self.prevrandao {
+            set_prevrandao(&mut executor.env.block, prevrandao);
+        }
+
+        let executor = Arc::new(Mutex::new(executor));
+        self.executor = Some(executor.clone());
+        Ok(executor)
+    }
+
+    /// Deploys the creation code on the shared executor, running `setUp` if the contract has
+    /// one.
+    fn deploy_code(
+        &self,
+        shared: Arc<Mutex<Executor>>,
+        name: &str,
+        abi: Abi,
+        code: Bytes,
+        libs: &[Bytes],
+        value: U256,
+    ) -> Result<Contract, RunnerError> {
+        let runner = &self.runner;
+        let sender = runner.sender.unwrap_or_default();
+
+        let mut executor = shared.lock().unwrap();
+        deploy_libraries(&mut executor, sender, libs, runner.errors.as_ref())?;
+        skip_occupied_nonces(&mut executor, sender)?;
+        let balance = executor.get_balance(sender).map_err(setup_err)?;
+        if balance < value {
+            executor.set_balance(sender, value).map_err(setup_err)?;
+        }
+        let deployment = executor
+            .deploy(sender, code.0, value, runner.errors.as_ref())
+            .map_err(setup_err)?;
+        let address = deployment.address;
+        let mut setup = TestSetup {
+            address,
+            logs: deployment.logs,
+            traces: deployment
+                .traces
+                .map(|traces| (TraceKind::Deployment, traces))
+                .into_iter()
+                .collect(),
+            coverage: deployment.coverage,
+            ..Default::default()
+        };
+        self.run_setup(&mut executor, &abi, value, &mut setup)?;
+        drop(executor);
+
+        let mut contract = Contract::new(self, shared, name, abi, address).map_err(setup_err)?;
+        contract.record_deployment(deployment.gas_used, setup);
+        Ok(contract)
+    }
+
+    /// Funds the freshly deployed contract, unless it was sent value on deployment, and runs its
+    /// `setUp` if it has one, adding its logs, trace and labels to the setup.
+    fn run_setup(
+        &self,
+        executor: &mut Executor,
+        abi: &Abi,
+        value: U256,
+        setup: &mut TestSetup,
+    ) -> Result<(), RunnerError> {
+        // contracts funded by their deployment keep exactly the value they were sent.
+        if value.is_zero() {
+            executor
+                .set_balance(setup.address, self.runner.evm_opts.initial_balance)
+                .map_err(setup_err)?;
+        }
+        if abi.functions.contains_key("setUp") {
+            let call = executor.setup(None, setup.address).map_err(setup_err)?;
+            setup.logs.extend(call.logs);
+            setup.traces.extend(call.traces.map(|traces| (TraceKind::Setup, traces)));
+            setup.labeled_addresses.extend(call.labels);
+        }
+        Ok(())
+    }
+}
+
+/// Deploys the libraries at the addresses the contract was linked against, libraries already
+/// deployed by an earlier contract are reused. The nonce of `sender` is left as it was.
+fn deploy_libraries(
+    executor: &mut Executor,
+    sender: Address,
+    libs: &[Bytes],
+    errors: Option<&Abi>,
+) -> Result<(), RunnerError> {
+    // mirror the forge setup, where libraries are linked starting from nonce 1.
+    let mut missing = vec![];
+    for (i, lib) in libs.iter().enumerate() {
+        let lib_nonce = 1 + i as u64;
+        if !has_code(executor, get_contract_address(sender, lib_nonce))? {
+            missing.push((lib_nonce, lib));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(())
+    }
+
+    let nonce = account_info(executor, sender)?.nonce;
+    for (lib_nonce, lib) in missing {
+        executor.set_nonce(sender, lib_nonce).map_err(setup_err)?;
+        executor
+            .deploy(sender, lib.0.clone(), U256::zero(), errors)
+            .map_err(setup_err)?;
+    }
+    executor.set_nonce(sender, nonce).map_err(setup_err)?;
+    Ok(())
+}
+
+/// Moves the nonce of `sender` past the nonces whose CREATE address already holds code, e.g. a
+/// linked library, since deploying there fails with a collision.
+fn skip_occupied_nonces(executor: &mut Executor, sender: Address) -> Result<(), RunnerError> {
+    let nonce = account_info(executor, sender)?.nonce;
+    let mut next = nonce;
+    while has_code(executor, get_contract_address(sender, next))? {
+        next += 1;
+    }
+    if next != nonce {
+        executor.set_nonce(sender, next).map_err(setup_err)?;
+    }
+    Ok(())
+}
+
+/// Whether there is code deployed at `address`.
+fn has_code(executor: &Executor, address: Address) -> Result<bool, RunnerError> {
+    Ok(account_info(executor, address)?.code_hash != KECCAK_EMPTY)
+}
+
+/// Sets `block.prevrandao`, along with `block.difficulty` which takes its place before the
+/// merge.
+pub(crate) fn set_prevrandao(block: &mut BlockEnv, prevrandao: H256) {
+    block.prevrandao = Some(h256_to_b256(prevrandao));
+    block.difficulty = u256_to_ru256(U256::from_big_endian(prevrandao.as_bytes()));
+}
+
+/// The account at `address`, or an empty account if it doesn't exist.
+pub(crate) fn account_info(
+    executor: &Executor,
+    address: Address,
+) -> Result<AccountInfo, RunnerError> {
+    let account = executor.backend.basic(h160_to_b160(address)).map_err(setup_err)?;
+    Ok(account.unwrap_or_default())
+}
+
+fn setup_err(err: impl Into<eyre::Report>) -> RunnerError {
+    RunnerError::Setup(err.into().to_string())
+}
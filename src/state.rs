@@ -0,0 +1,122 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The accounts and storage slots changed and accessed by a call.
+
+use crate::utils::word;
+use ethers::types::{H256, U256};
+use foundry_evm::{
+    executor::StateChangeset,
+    revm::db::DatabaseRef,
+    utils::{b160_to_h160, ru256_to_u256},
+    Address,
+};
+use std::collections::BTreeMap;
+
+/// The value of a piece of state before and after a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delta<T> {
+    /// The value before the call
+    pub before: T,
+    /// The value after the call
+    pub after: T,
+}
+
+impl<T: PartialEq> Delta<T> {
+    /// Whether the call changed the value.
+    pub fn changed(&self) -> bool {
+        self.before != self.after
+    }
+}
+
+/// The changes a call made to a single account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+    /// The ether balance of the account
+    pub balance: Delta<U256>,
+    /// The nonce of the account
+    pub nonce: Delta<u64>,
+    /// The storage slots whose value changed, by slot
+    pub storage: BTreeMap<H256, Delta<H256>>,
+}
+
+/// The accounts touched by a call, along with their balance, nonce and storage changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// The changes made to every touched account, by address
+    pub accounts: BTreeMap<Address, AccountDiff>,
+}
+
+impl StateDiff {
+    /// Diffs the changes recorded by the executor against the state the call ran on.
+    pub(crate) fn new<DB: DatabaseRef>(pre: &DB, changes: &StateChangeset) -> Self {
+        let accounts = changes
+            .iter()
+            .map(|(address, account)| {
+                let before = pre.basic(*address).ok().flatten().unwrap_or_default();
+                let storage = account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| slot.original_value != slot.present_value)
+                    .map(|(slot, value)| {
+                        let delta = Delta {
+                            before: word(ru256_to_u256(value.original_value)),
+                            after: word(ru256_to_u256(value.present_value)),
+                        };
+                        (word(ru256_to_u256(*slot)), delta)
+                    })
+                    .collect();
+                let diff = AccountDiff {
+                    balance: Delta {
+                        before: ru256_to_u256(before.balance),
+                        after: ru256_to_u256(account.info.balance),
+                    },
+                    nonce: Delta { before: before.nonce, after: account.info.nonce },
+                    storage,
+                };
+                (b160_to_h160(*address), diff)
+            })
+            .collect();
+
+        Self { accounts }
+    }
+
+    /// The changes made to the account at `address`, if the call touched it.
+    pub fn get(&self, address: Address) -> Option<&AccountDiff> {
+        self.accounts.get(&address)
+    }
+
+    /// The storage slots of `address` changed by the call.
+    pub fn storage(&self, address: Address) -> BTreeMap<H256, Delta<H256>> {
+        self.get(address).map(|account| account.storage.clone()).unwrap_or_default()
+    }
+}
+
+/// The storage slots read and written by a call, recorded with
+/// [`Contract::record_accesses`](crate::Contract::record_accesses).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountAccesses {
+    /// The slots accessed in the storage of every touched account, by address
+    pub accounts: BTreeMap<Address, StorageAccesses>,
+}
+
+/// The storage slots of a single account accessed by a call, in access order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageAccesses {
+    /// The slots read with `SLOAD`, including the ones read by `SSTORE`
+    pub reads: Vec<H256>,
+    /// The slots written with `SSTORE`
+    pub writes: Vec<H256>,
+}
@@ -0,0 +1,120 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Standalone helpers that don't need a runner.
+
+use ethers::{
+    signers::{LocalWallet, WalletError},
+    types::{Address, Signature, SignatureError, H256, U256},
+    utils::{self, get_contract_address, get_create2_address_from_hash, ConversionError},
+};
+
+/// Computes the address a contract is deployed at through CREATE by `deployer` when its nonce
+/// is `nonce`.
+pub fn compute_create_address(deployer: Address, nonce: u64) -> Address {
+    get_contract_address(deployer, nonce)
+}
+
+/// Computes the address a contract is deployed at through CREATE2 by `deployer`, given the salt
+/// and the keccak256 hash of its (library-linked) creation code.
+pub fn compute_create2_address(deployer: Address, salt: H256, init_code_hash: H256) -> Address {
+    get_create2_address_from_hash(deployer, salt, init_code_hash)
+}
+
+/// Computes the storage slot of the entry for `key` in a mapping stored at `base_slot`. The key
+/// must be abi encoded to 32 bytes, e.g. a left padded address.
+pub fn compute_mapping_slot(base_slot: H256, key: H256) -> H256 {
+    keccak256([key.as_bytes(), base_slot.as_bytes()].concat())
+}
+
+/// Hashes the data with keccak256.
+pub fn keccak256(data: impl AsRef<[u8]>) -> H256 {
+    utils::keccak256(data).into()
+}
+
+/// Signs the 32 byte digest with the secp256k1 private key, like `vm.sign`. The digest is
+/// signed as is, without the EIP-191 prefix.
+pub fn sign(private_key: H256, digest: H256) -> Result<Signature, WalletError> {
+    LocalWallet::from_bytes(private_key.as_bytes())?.sign_hash(digest)
+}
+
+/// Recovers the address that signed the 32 byte digest, the counterpart of [`sign`] and
+/// solidity's `ecrecover`.
+pub fn recover(digest: H256, signature: &Signature) -> Result<Address, SignatureError> {
+    signature.recover(digest)
+}
+
+/// Parses a decimal amount of ether, e.g. `"1.5"`, into wei.
+pub fn parse_ether(amount: &str) -> Result<U256, ConversionError> {
+    utils::parse_ether(amount)
+}
+
+/// Parses a decimal amount of a token with the given number of decimals into its base units,
+/// e.g. `parse_units("2.5", 6)` for 2.5 USDC.
+pub fn parse_units(amount: &str, decimals: u32) -> Result<U256, ConversionError> {
+    Ok(utils::parse_units(amount, decimals)?.into())
+}
+
+/// Encodes the value as a 32 byte storage word.
+pub(crate) fn word(value: U256) -> H256 {
+    let mut word = H256::zero();
+    value.to_big_endian(word.as_bytes_mut());
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_create_addresses() {
+        let deployer = "0x6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0".parse().unwrap();
+        let expected: Address = "0xcd234a471b72ba2f1ccf0a70fcaba648a5eecd8d".parse().unwrap();
+        assert_eq!(compute_create_address(deployer, 0), expected);
+        let expected: Address = "0x343c43a37d37dff08ae8c4a11544c718abb4fcf8".parse().unwrap();
+        assert_eq!(compute_create_address(deployer, 1), expected);
+    }
+
+    #[test]
+    fn computes_mapping_slots() {
+        let expected: H256 = "0xad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+            .parse()
+            .unwrap();
+        assert_eq!(compute_mapping_slot(H256::zero(), H256::zero()), expected);
+
+        let key = H256::from(Address::repeat_byte(0x11));
+        let slot = word(U256::from(3));
+        assert_eq!(compute_mapping_slot(slot, key), keccak256([key.0, slot.0].concat()));
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse_units("2.5", 6).unwrap(), U256::from(2_500_000));
+        assert_eq!(parse_units("1", 0).unwrap(), U256::one());
+        assert_eq!(parse_ether("1.5").unwrap(), U256::from(1_500_000_000_000_000_000u64));
+        assert!(parse_units("one", 6).is_err());
+    }
+
+    #[test]
+    fn recovers_signer() {
+        let private_key = H256::from_low_u64_be(1);
+        let signer: Address = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf".parse().unwrap();
+        let digest = keccak256("forge-testsuite");
+
+        let signature = sign(private_key, digest).unwrap();
+        assert_eq!(recover(digest, &signature).unwrap(), signer);
+        assert_ne!(recover(keccak256("other"), &signature).unwrap(), signer);
+    }
+}